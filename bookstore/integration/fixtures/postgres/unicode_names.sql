@@ -0,0 +1,3 @@
+INSERT INTO book (id, name, description) VALUES
+    ('0192a7c4-5b7e-7c3a-9f1e-2d6b8c4e1a02', '百年孤独', 'Cien años de soledad, in Chinese'),
+    ('0192a7c4-5b7e-7c3a-9f1e-2d6b8c4e1a03', 'Die Blechtrommel 🥁', 'Oskar Matzerath refuses to grow up');
@@ -0,0 +1,3 @@
+INSERT INTO book (id, name, description) VALUES
+    (X'0192a7c45b7e7c3a9f1e2d6b8c4e1a02', '百年孤独', 'Cien años de soledad, in Chinese'),
+    (X'0192a7c45b7e7c3a9f1e2d6b8c4e1a03', 'Die Blechtrommel 🥁', 'Oskar Matzerath refuses to grow up');
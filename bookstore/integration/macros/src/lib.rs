@@ -0,0 +1,123 @@
+use proc_macro::TokenStream;
+use proc_macro2::TokenStream as TokenStream2;
+use quote::quote;
+use syn::{
+    ItemFn, LitInt, LitStr, Token, meta::ParseNestedMeta, parenthesized, parse_macro_input,
+    punctuated::Punctuated,
+};
+
+/// Registers an `async fn(TestHarness) -> color_eyre::Result<()>` as a test of the integration
+/// harness. The test is named after the path of the function, without the crate, e.g.
+/// `bookstore_test::registering_success`.
+///
+/// Takes these options, all optional:
+/// - `ignore`: only runs with `--ignored` or `--include-ignored`
+/// - `timeout = <seconds>`: how long the test may run, instead of `--test-timeout`
+/// - `tags("slow", ...)`: the test runs with `--tag` for any of these
+/// - `should_fail`: the test passes by returning an error or panicking
+/// - `fixtures("books", ...)`: loaded into the database of the test before it runs
+///
+/// The harness is expected at `crate::testharness`.
+#[proc_macro_attribute]
+pub fn integration_test(args: TokenStream, item: TokenStream) -> TokenStream {
+    let mut options = TestOptions::default();
+    let parser = syn::meta::parser(|meta| options.parse(meta));
+    parse_macro_input!(args with parser);
+    let function = parse_macro_input!(item as ItemFn);
+    expand(options, function)
+        .unwrap_or_else(syn::Error::into_compile_error)
+        .into()
+}
+
+#[derive(Default)]
+struct TestOptions {
+    ignore: bool,
+    timeout: Option<LitInt>,
+    tags: Vec<LitStr>,
+    should_fail: bool,
+    fixtures: Vec<LitStr>,
+}
+
+impl TestOptions {
+    fn parse(&mut self, meta: ParseNestedMeta) -> syn::Result<()> {
+        if meta.path.is_ident("ignore") {
+            self.ignore = true;
+        } else if meta.path.is_ident("should_fail") {
+            self.should_fail = true;
+        } else if meta.path.is_ident("timeout") {
+            let seconds: LitInt = meta.value()?.parse()?;
+            if seconds.base10_parse::<u64>()? == 0 {
+                return Err(syn::Error::new(seconds.span(), "the timeout can't be 0"));
+            }
+            self.timeout = Some(seconds);
+        } else if meta.path.is_ident("tags") {
+            self.tags.extend(parse_strings(&meta)?);
+        } else if meta.path.is_ident("fixtures") {
+            self.fixtures.extend(parse_strings(&meta)?);
+        } else {
+            return Err(meta.error("expected ignore, timeout, tags, should_fail or fixtures"));
+        }
+        Ok(())
+    }
+}
+
+/// The strings of an option like `tags("a", "b")`
+fn parse_strings(meta: &ParseNestedMeta) -> syn::Result<Punctuated<LitStr, Token![,]>> {
+    let content;
+    parenthesized!(content in meta.input);
+    Punctuated::parse_terminated(&content)
+}
+
+fn expand(options: TestOptions, function: ItemFn) -> syn::Result<TokenStream2> {
+    let signature = &function.sig;
+    if signature.asyncness.is_none() {
+        return Err(syn::Error::new_spanned(
+            signature.fn_token,
+            "integration tests have to be async",
+        ));
+    }
+    if signature.inputs.len() != 1 {
+        return Err(syn::Error::new_spanned(
+            &signature.inputs,
+            "integration tests take the TestHarness as their only argument",
+        ));
+    }
+
+    let name = &signature.ident;
+    let TestOptions {
+        ignore,
+        timeout,
+        tags,
+        should_fail,
+        fixtures,
+    } = options;
+    let timeout = match timeout {
+        Some(seconds) => quote!(::std::option::Option::Some(
+            ::std::time::Duration::from_secs(#seconds)
+        )),
+        None => quote!(::std::option::Option::None),
+    };
+    Ok(quote! {
+        #function
+
+        const _: () = {
+            fn run(harness: crate::testharness::TestHarness) -> crate::testharness::TestReturn {
+                ::std::boxed::Box::pin(#name(harness))
+            }
+
+            ::inventory::submit!(crate::testharness::IntegrationTestCase {
+                name: crate::testharness::test_name(::std::concat!(
+                    ::std::module_path!(),
+                    "::",
+                    ::std::stringify!(#name)
+                )),
+                fun: run,
+                ignore: #ignore,
+                timeout: #timeout,
+                tags: &[#(#tags),*],
+                should_fail: #should_fail,
+                fixtures: &[#(#fixtures),*],
+            });
+        };
+    })
+}
@@ -1,63 +1,246 @@
-use axum::http::{Request, StatusCode};
+use std::future::IntoFuture;
+
+use axum::http::StatusCode;
 use bookstore::{
-    appstate::{AppState, Book},
-    create_router,
+    appstate::Book,
+    database::Database,
     handlers::BookRegistration,
+    util::{FieldError, ProblemDetails},
 };
-use tower::ServiceExt;
-
-use crate::testharness::{IntegrationTestCase, TestHarness, TestReturn};
-
-pub fn test_book_registering(harness: TestHarness) -> TestReturn {
-    Box::pin(async move {
-        let state = AppState::new(harness.connection);
-        let app = create_router(state);
-        let body = serde_json::to_string(&BookRegistration {
-            name: String::from("Ship of Theseus"),
-            description: String::from(
-                "Elaborate book about two people tracking down a mysterious author",
-            ),
-        })?;
-        let request = Request::post("/book")
-            .header("content-type", "application/json")
-            .body(body)?;
-        let response = app.oneshot(request).await?;
-        let (parts, body) = response.into_parts();
-        let bytes = axum::body::to_bytes(body, usize::MAX).await?;
-        assert_eq!(parts.status, StatusCode::OK);
-        let converted = serde_json::from_slice::<Book>(&bytes)?;
-        assert_eq!(converted.name, "Ship of Theseus");
-        Ok(())
-    })
-}
-
-pub fn test_registering_conflict(harness: TestHarness) -> TestReturn {
-    Box::pin(async move {
-        let state = AppState::new(harness.connection);
-        let app = create_router(state);
-        let body = serde_json::to_string(&BookRegistration {
-            name: String::from("Ship of Theseus"),
-            description: String::from(
-                "Elaborate book about two people tracking down a mysterious author",
-            ),
-        })?;
-        let request = Request::post("/book")
-            .header("content-type", "application/json")
-            .body(body)?;
-        let _ = app.clone().oneshot(request.clone()).await?;
-        let response = app.oneshot(request).await?;
-        let (parts, _body) = response.into_parts();
-        assert_eq!(parts.status, StatusCode::CONFLICT);
-        Ok(())
-    })
-}
-
-inventory::submit!(IntegrationTestCase {
-    name: "book_registering_success",
-    fun: test_book_registering,
-});
-
-inventory::submit!(IntegrationTestCase {
-    name: "book_registering_conflict",
-    fun: test_registering_conflict,
-});
+use integration_macros::integration_test;
+use uuid::Uuid;
+
+use crate::testharness::{TestHarness, factories::BookFactory};
+
+fn ship_of_theseus() -> BookRegistration {
+    BookRegistration {
+        name: String::from("Ship of Theseus"),
+        description: String::from(
+            "Elaborate book about two people tracking down a mysterious author",
+        ),
+    }
+}
+
+#[integration_test]
+async fn book_registering_success(harness: TestHarness) -> color_eyre::Result<()> {
+    let book = harness
+        .app()
+        .post_json("/v1/book", &ship_of_theseus())
+        .await
+        .assert_status(StatusCode::OK)
+        .json::<Book>();
+    assert_eq!(book.name, "Ship of Theseus");
+    Ok(())
+}
+
+#[integration_test]
+async fn book_registering_conflict(harness: TestHarness) -> color_eyre::Result<()> {
+    let app = harness.app();
+    app.post_json("/v1/book", &ship_of_theseus())
+        .await
+        .assert_status(StatusCode::OK);
+    let problem = app
+        .post_json("/v1/book", &ship_of_theseus())
+        .await
+        .assert_status(StatusCode::CONFLICT)
+        .json::<ProblemDetails>();
+    assert_eq!(problem.status, 409);
+    assert_eq!(problem.instance.as_deref(), Some("/v1/book"));
+    Ok(())
+}
+
+#[integration_test]
+async fn book_registering_concurrently(harness: TestHarness) -> color_eyre::Result<()> {
+    let app = harness.app();
+    let registration = BookRegistration {
+        name: String::from("House of Leaves"),
+        description: String::from("A book about a house that is bigger on the inside"),
+    };
+    let requests = (0..8).map(|_| app.post_json("/v1/book", &registration).into_future());
+    let mut statuses = futures::future::join_all(requests)
+        .await
+        .into_iter()
+        .map(|response| response.status())
+        .collect::<Vec<_>>();
+    statuses.sort();
+    let mut expected = vec![StatusCode::CONFLICT; 7];
+    expected.insert(0, StatusCode::OK);
+    assert_eq!(statuses, expected);
+    Ok(())
+}
+
+#[integration_test]
+async fn book_registering_invalid(harness: TestHarness) -> color_eyre::Result<()> {
+    let problem = harness
+        .app()
+        .post_json("/v1/book", &serde_json::json!({ "name": "   " }))
+        .await
+        .assert_status(StatusCode::UNPROCESSABLE_ENTITY)
+        .json::<ProblemDetails>();
+    assert_eq!(
+        problem.errors,
+        vec![
+            FieldError {
+                field: "name".to_string(),
+                message: "is required".to_string(),
+            },
+            FieldError {
+                field: "description".to_string(),
+                message: "is required".to_string(),
+            },
+        ]
+    );
+    Ok(())
+}
+
+#[integration_test(tags("smoke"))]
+async fn service_ready(harness: TestHarness) -> color_eyre::Result<()> {
+    harness
+        .app()
+        .get("/ready")
+        .await
+        .assert_status(StatusCode::NO_CONTENT);
+    Ok(())
+}
+
+#[integration_test(tags("smoke"))]
+async fn show_missing_book(harness: TestHarness) -> color_eyre::Result<()> {
+    let id = Uuid::new_v4();
+    harness
+        .app()
+        .get(&format!("/v1/book/{id}"))
+        .await
+        .assert_status(StatusCode::NOT_FOUND)
+        .assert_header("content-type", "application/problem+json")
+        .assert_json(&serde_json::json!({
+            "type": "about:blank",
+            "title": "Not Found",
+            "status": 404,
+            "detail": format!("Cannot find book with id {id}"),
+            "instance": format!("/v1/book/{id}"),
+        }));
+    Ok(())
+}
+
+#[integration_test(tags("smoke"))]
+async fn show_book_invalid_id(harness: TestHarness) -> color_eyre::Result<()> {
+    let problem = harness
+        .app()
+        .get("/v1/book/not-a-uuid")
+        .await
+        .assert_status(StatusCode::BAD_REQUEST)
+        .assert_header("content-type", "application/problem+json")
+        .json::<ProblemDetails>();
+    assert_eq!(problem.status, 400);
+    assert_eq!(problem.instance.as_deref(), Some("/v1/book/not-a-uuid"));
+    Ok(())
+}
+
+#[integration_test(tags("smoke"))]
+async fn unversioned_routes_deprecated(harness: TestHarness) -> color_eyre::Result<()> {
+    let app = harness.app();
+    app.get("/book")
+        .await
+        .assert_status(StatusCode::OK)
+        .assert_header("deprecation", "@1792368000")
+        .assert_header("sunset", "Mon, 19 Apr 2027 00:00:00 GMT");
+    app.get("/v1/book")
+        .await
+        .assert_status(StatusCode::OK)
+        .assert_no_header("deprecation");
+    Ok(())
+}
+
+#[integration_test(fixtures("library"))]
+async fn list_books_from_fixture(harness: TestHarness) -> color_eyre::Result<()> {
+    let app = harness.app();
+    let mut names = app
+        .get("/v1/book")
+        .await
+        .assert_status(StatusCode::OK)
+        .json::<Vec<Book>>()
+        .into_iter()
+        .map(|book| book.name)
+        .collect::<Vec<_>>();
+    names.sort();
+    assert_eq!(
+        names,
+        ["Gödel, Escher, Bach", "House of Leaves", "Ship of Theseus"]
+    );
+    app.get("/v1/book/0192a7c4-5b7e-7c3a-9f1e-2d6b8c4e1a01")
+        .await
+        .assert_status(StatusCode::OK)
+        .assert_json(&serde_json::json!({
+            "id": "0192a7c4-5b7e-7c3a-9f1e-2d6b8c4e1a01",
+            "name": "Ship of Theseus",
+            "description": "Elaborate book about two people tracking down a mysterious author",
+            "updated_at": "2025-10-20T09:00:00Z",
+        }));
+    Ok(())
+}
+
+#[integration_test(fixtures("unicode_names"))]
+async fn show_book_from_sql_fixture(harness: TestHarness) -> color_eyre::Result<()> {
+    let book = harness
+        .app()
+        .get("/v1/book/0192a7c4-5b7e-7c3a-9f1e-2d6b8c4e1a03")
+        .await
+        .assert_status(StatusCode::OK)
+        .json::<Book>();
+    assert_eq!(book.name, "Die Blechtrommel 🥁");
+    Ok(())
+}
+
+#[integration_test]
+async fn list_many_books(harness: TestHarness) -> color_eyre::Result<()> {
+    let books = BookFactory::new().insert_many(50, &harness).await?;
+    let book = BookFactory::new()
+        .name("Ship of Theseus")
+        .insert(&harness)
+        .await?;
+
+    let app = harness.app();
+    let listed = app
+        .get("/v1/book")
+        .await
+        .assert_status(StatusCode::OK)
+        .json::<Vec<Book>>();
+    assert_eq!(listed.len(), books.len() + 1);
+    app.get(&format!("/v1/book/{}", book.id))
+        .await
+        .assert_status(StatusCode::OK)
+        .assert_json(&book);
+    Ok(())
+}
+
+/// Postgres moves an updated row to the end of the table, which doesn't move it in the list
+#[integration_test]
+async fn list_books_in_registration_order(harness: TestHarness) -> color_eyre::Result<()> {
+    let app = harness.app();
+    let names = ["Ship of Theseus", "House of Leaves", "Gödel, Escher, Bach"];
+    for name in names {
+        let registration = BookRegistration {
+            name: name.to_string(),
+            description: String::from("A book"),
+        };
+        app.post_json("/v1/book", &registration)
+            .await
+            .assert_status(StatusCode::OK);
+    }
+    let update = "UPDATE book SET description = 'Another book' WHERE name = 'Ship of Theseus'";
+    match &harness.database {
+        Database::Postgres(pool) => sqlx::raw_sql(update).execute(pool).await.map(drop),
+        Database::Sqlite(pool) => sqlx::raw_sql(update).execute(pool).await.map(drop),
+    }?;
+
+    let listed = app
+        .get("/v1/book")
+        .await
+        .assert_status(StatusCode::OK)
+        .json::<Vec<Book>>()
+        .into_iter()
+        .map(|book| book.name)
+        .collect::<Vec<_>>();
+    assert_eq!(listed, names);
+    Ok(())
+}
@@ -0,0 +1,153 @@
+use std::{sync::Arc, time::Duration};
+
+use axum::http::{Request, StatusCode};
+use bookstore::{
+    cache::{BookCache, CacheOptions, CacheStats, Source},
+    create_router,
+    database::Database,
+    handlers::BookRegistration,
+    repository::{
+        BookRepository, CachedBookRepository, InMemoryBookRepository, ReplicatedBookRepository,
+    },
+    unit_of_work::TransactionOptions,
+};
+use color_eyre::eyre::bail;
+use integration_macros::integration_test;
+use tower::ServiceExt;
+
+use crate::testharness::{TestHarness, skip};
+
+fn registration(name: &str) -> BookRegistration {
+    BookRegistration {
+        name: name.to_string(),
+        description: String::from("A book that gets cached"),
+    }
+}
+
+#[integration_test(tags("in_process"))]
+async fn cache_invalidation(harness: TestHarness) -> color_eyre::Result<()> {
+    let state = harness.app_state().with_cache(CacheOptions::default());
+    let cache = state.cache.clone().unwrap();
+    let books = &state.books;
+
+    let first = books.register_book(&registration("Dune")).await?.unwrap();
+    assert!(books.get_book_by_id(first.id).await?.is_some());
+    assert!(books.get_book_by_id(first.id).await?.is_some());
+    assert_eq!(books.list_books().await?.len(), 1);
+    assert_eq!(books.list_books().await?.len(), 1);
+    assert_eq!(cache.stats(), CacheStats { hits: 2, misses: 2 });
+
+    books.register_book(&registration("Hyperion")).await?;
+    assert_eq!(books.list_books().await?.len(), 2);
+
+    state
+        .transaction(TransactionOptions::default(), |uow| {
+            Box::pin(async move {
+                uow.books.register_book(&registration("Solaris")).await?;
+                Ok(())
+            })
+        })
+        .await?;
+    assert_eq!(books.list_books().await?.len(), 3);
+    assert_eq!(cache.stats(), CacheStats { hits: 2, misses: 4 });
+
+    let app = create_router(state.clone());
+    let request = Request::get("/metrics").body(String::new())?;
+    let response = app.oneshot(request).await?;
+    assert_eq!(response.status(), StatusCode::OK);
+    let bytes = axum::body::to_bytes(response.into_body(), usize::MAX).await?;
+    let metrics = String::from_utf8(bytes.to_vec())?;
+    assert!(metrics.contains("bookstore_cache_hits_total 2\n"));
+    assert!(metrics.contains("bookstore_cache_misses_total 4\n"));
+    Ok(())
+}
+
+/// Another instance writing to the same database invalidates the cache through the notifications
+#[integration_test(tags("in_process"))]
+async fn cache_notify_invalidation(harness: TestHarness) -> color_eyre::Result<()> {
+    let Database::Postgres(pool) = &harness.database else {
+        return skip("Only postgres can notify");
+    };
+    let options = CacheOptions {
+        ttl: Duration::from_secs(600),
+        ..CacheOptions::default()
+    };
+    let state = harness.app_state().with_cache(options);
+    let cache = state.cache.clone().unwrap();
+    let other_instance = harness.app_state();
+
+    // The listener invalidates everything once it is listening
+    let generation = cache.generation();
+    let listener = cache.listen(pool.clone());
+    eventually(|| async { Ok(cache.generation() > generation) }).await?;
+
+    assert!(state.books.list_books().await?.is_empty());
+    other_instance
+        .books
+        .register_book(&registration("Dune"))
+        .await?;
+    let invalidated = eventually(|| async { Ok(state.books.list_books().await?.len() == 1) }).await;
+    listener.abort();
+    invalidated
+}
+
+/// The invalidation of a write gets there before the replica has the write. What the replica
+/// answers until it catches up isn't cached, or it would be served for the whole TTL.
+#[integration_test(tags("in_process"))]
+async fn cache_replica_reads_after_invalidation(_harness: TestHarness) -> color_eyre::Result<()> {
+    let cache = BookCache::new(CacheOptions {
+        ttl: Duration::from_secs(600),
+        replica_lag: Duration::from_millis(300),
+        ..CacheOptions::default()
+    });
+    let primary = InMemoryBookRepository::new();
+    let replica = InMemoryBookRepository::new();
+    let cached = |books: &InMemoryBookRepository, source| {
+        Arc::new(CachedBookRepository::new(
+            Arc::new(books.clone()),
+            cache.clone(),
+            source,
+        ))
+    };
+    let books = ReplicatedBookRepository::new(
+        cached(&primary, Source::Primary),
+        cached(&replica, Source::Replica),
+        Duration::ZERO,
+    );
+
+    books.register_book(&registration("Dune")).await?;
+    assert!(books.list_books().await?.is_empty());
+    // The replica catches up
+    replica.register_book(&registration("Dune")).await?;
+    assert_eq!(books.list_books().await?.len(), 1);
+    assert_eq!(cache.stats(), CacheStats { hits: 0, misses: 2 });
+
+    // Once the replica had the time to catch up, what it answers is cached again
+    tokio::time::sleep(Duration::from_millis(400)).await;
+    assert_eq!(books.list_books().await?.len(), 1);
+    assert_eq!(books.list_books().await?.len(), 1);
+    assert_eq!(cache.stats(), CacheStats { hits: 1, misses: 3 });
+
+    // Reads of the primary are cached right away
+    let primary = CachedBookRepository::new(Arc::new(primary), cache.clone(), Source::Primary);
+    primary.register_book(&registration("Hyperion")).await?;
+    assert_eq!(primary.list_books().await?.len(), 2);
+    assert_eq!(primary.list_books().await?.len(), 2);
+    assert_eq!(cache.stats(), CacheStats { hits: 2, misses: 4 });
+    Ok(())
+}
+
+/// Waits until `condition` holds, failing after a few seconds
+async fn eventually<F, Fut>(condition: F) -> color_eyre::Result<()>
+where
+    F: Fn() -> Fut,
+    Fut: Future<Output = color_eyre::Result<bool>>,
+{
+    for _ in 0..50 {
+        if condition().await? {
+            return Ok(());
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+    bail!("The condition did not hold in time")
+}
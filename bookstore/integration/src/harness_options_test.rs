@@ -0,0 +1,146 @@
+use integration_macros::integration_test;
+
+use crate::testharness::{
+    TestHarness,
+    options::{HarnessOptions, OutputFormat},
+    testselector::select_tests,
+};
+
+fn parse(args: &[&str]) -> color_eyre::Result<HarnessOptions> {
+    HarnessOptions::parse(args.iter().map(|arg| arg.to_string()))
+}
+
+fn selected(options: &HarnessOptions) -> Vec<&'static str> {
+    select_tests(options)
+        .run
+        .iter()
+        .map(|case| case.name)
+        .collect()
+}
+
+#[integration_test(tags("in_process"))]
+async fn harness_options_filters(_harness: TestHarness) -> color_eyre::Result<()> {
+    let options = parse(&["harness_options_", "openapi"])?;
+    assert_eq!(options.filters, ["harness_options_", "openapi"]);
+
+    let names = selected(&options);
+    assert!(names.contains(&"harness_options_test::harness_options_filters"));
+    assert!(names.contains(&"openapi_test::openapi_spec_up_to_date"));
+    assert!(
+        !names
+            .iter()
+            .any(|name| name.starts_with("bookstore_test::"))
+    );
+    Ok(())
+}
+
+#[integration_test(tags("in_process"))]
+async fn harness_options_skip(_harness: TestHarness) -> color_eyre::Result<()> {
+    let options = parse(&[
+        "harness_options_",
+        "--skip",
+        "harness_options_exact",
+        "--skip=harness_options_format",
+    ])?;
+    assert_eq!(
+        options.skip,
+        ["harness_options_exact", "harness_options_format"]
+    );
+
+    let names = selected(&options);
+    assert!(names.contains(&"harness_options_test::harness_options_skip"));
+    assert!(
+        !names
+            .iter()
+            .any(|name| name.contains("harness_options_exact"))
+    );
+    assert!(
+        !names
+            .iter()
+            .any(|name| name.contains("harness_options_format"))
+    );
+
+    let error = parse(&["--skip"]).unwrap_err();
+    assert_eq!(error.to_string(), "Option --skip needs a value");
+    Ok(())
+}
+
+#[integration_test(tags("in_process"))]
+async fn harness_options_exact(_harness: TestHarness) -> color_eyre::Result<()> {
+    let options = parse(&["--exact", "harness_options_exact"])?;
+    assert!(options.exact);
+    assert!(selected(&options).is_empty());
+
+    let options = parse(&["--exact", "harness_options_test::harness_options_exact"])?;
+    assert_eq!(
+        selected(&options),
+        ["harness_options_test::harness_options_exact"]
+    );
+
+    let options = parse(&[
+        "--exact",
+        "harness_options_",
+        "--skip",
+        "harness_options_test::harness_options_exact",
+    ])?;
+    assert!(selected(&options).is_empty());
+    Ok(())
+}
+
+#[integration_test(tags("in_process"))]
+async fn harness_options_format(_harness: TestHarness) -> color_eyre::Result<()> {
+    assert_eq!(parse(&[])?.format, OutputFormat::Pretty);
+    assert_eq!(parse(&["--format", "pretty"])?.format, OutputFormat::Pretty);
+    assert_eq!(parse(&["--format=terse"])?.format, OutputFormat::Terse);
+    assert_eq!(parse(&["-q"])?.format, OutputFormat::Terse);
+    assert_eq!(parse(&["--format", "json"])?.format, OutputFormat::Json);
+
+    let error = parse(&["--format", "junit"]).unwrap_err();
+    assert_eq!(
+        error.to_string(),
+        "Unsupported format junit, expected pretty, terse or json"
+    );
+    Ok(())
+}
+
+#[integration_test(tags("in_process"))]
+async fn harness_options_unstable_flags(_harness: TestHarness) -> color_eyre::Result<()> {
+    let options = parse(&["-Zunstable-options", "--format", "json", "list_books"])?;
+    assert_eq!(options.format, OutputFormat::Json);
+    assert_eq!(options.filters, ["list_books"]);
+
+    let options = parse(&["-Z", "unstable-options", "--format=json", "list_books"])?;
+    assert_eq!(options.format, OutputFormat::Json);
+    assert_eq!(options.filters, ["list_books"]);
+    Ok(())
+}
+
+#[integration_test(tags("in_process"))]
+async fn harness_options_ignored_libtest_flags(_harness: TestHarness) -> color_eyre::Result<()> {
+    let options = parse(&[
+        "--report-time",
+        "--ensure-time",
+        "--shuffle",
+        "--shuffle-seed",
+        "42",
+        "--logfile=/tmp/tests.log",
+        "--color",
+        "never",
+        "--exclude-should-panic",
+        "--show-output",
+        "--no-capture",
+        "list_books",
+    ])?;
+    assert_eq!(options.filters, ["list_books"]);
+    assert!(options.show_output);
+    assert!(options.nocapture);
+
+    let error = parse(&["--shuffle-seed"]).unwrap_err();
+    assert_eq!(error.to_string(), "Option --shuffle-seed needs a value");
+    let error = parse(&["--bogus"]).unwrap_err();
+    assert_eq!(
+        error.to_string(),
+        "Unknown option --bogus, see --help for the supported ones"
+    );
+    Ok(())
+}
@@ -0,0 +1,153 @@
+use std::time::SystemTime;
+
+use axum::http::StatusCode;
+use bookstore::{
+    appstate::Book, database::Database, handlers::BookRegistration, http_cache::policy,
+};
+use chrono::Utc;
+use integration_macros::integration_test;
+
+use crate::testharness::{TestHarness, client::TestClient, factories::BookFactory};
+
+async fn register(app: &TestClient) -> Book {
+    app.post_json(
+        "/v1/book",
+        &BookRegistration {
+            name: String::from("Gödel, Escher, Bach"),
+            description: String::from("An eternal golden braid of formal systems, art and music"),
+        },
+    )
+    .await
+    .assert_status(StatusCode::OK)
+    .json()
+}
+
+#[integration_test]
+async fn http_response_compression(harness: TestHarness) -> color_eyre::Result<()> {
+    let app = harness.app();
+    register(&app).await;
+
+    for encoding in ["gzip", "br", "zstd"] {
+        app.get("/v1/book")
+            .header("accept-encoding", encoding)
+            .await
+            .assert_status(StatusCode::OK)
+            .assert_header("content-encoding", encoding)
+            .assert_header("vary", "accept-encoding");
+    }
+
+    app.get("/v1/book")
+        .await
+        .assert_no_header("content-encoding");
+    Ok(())
+}
+
+#[integration_test]
+async fn http_conditional_requests(harness: TestHarness) -> color_eyre::Result<()> {
+    let app = harness.app();
+    let book = register(&app).await;
+
+    for (path, policy) in [
+        (format!("/v1/book/{}", book.id), policy::BOOK),
+        (String::from("/v1/book"), policy::BOOK_LIST),
+    ] {
+        let response = app
+            .get(&path)
+            .await
+            .assert_status(StatusCode::OK)
+            .assert_header("cache-control", policy);
+        let etag = response.header("etag").expect("The response has an ETag");
+
+        let response = app
+            .get(&path)
+            .header("if-none-match", etag)
+            .await
+            .assert_status(StatusCode::NOT_MODIFIED)
+            .assert_header("cache-control", policy);
+        assert!(response.bytes().is_empty());
+
+        app.get(&path)
+            .header("if-none-match", r#"W/"0123456789abcdef""#)
+            .await
+            .assert_status(StatusCode::OK);
+    }
+
+    // Books never change, so their modification time validates them too
+    let path = format!("/v1/book/{}", book.id);
+    let response = app.get(&path).await;
+    let last_modified = response
+        .header("last-modified")
+        .expect("The response has a modification time");
+    app.get(&path)
+        .header("if-modified-since", last_modified)
+        .await
+        .assert_status(StatusCode::NOT_MODIFIED);
+    app.get(&path)
+        .header("if-modified-since", "Sun, 06 Nov 1994 08:49:37 GMT")
+        .await
+        .assert_status(StatusCode::OK);
+
+    app.get("/ready")
+        .await
+        .assert_header("cache-control", policy::NO_STORE);
+    Ok(())
+}
+
+/// The ETag of the list only tells whether it changed if the books are always listed in the same
+/// order. Postgres rewrites the table in the order of the names on CLUSTER, like a replica or a
+/// restore may keep the rows in an order of its own.
+#[integration_test]
+async fn http_list_not_modified_after_rewrite(harness: TestHarness) -> color_eyre::Result<()> {
+    let app = harness.app();
+    for name in ["Ship of Theseus", "House of Leaves", "Gödel, Escher, Bach"] {
+        let registration = BookRegistration {
+            name: name.to_string(),
+            description: String::from("A book"),
+        };
+        app.post_json("/v1/book", &registration)
+            .await
+            .assert_status(StatusCode::OK);
+    }
+    let response = app.get("/v1/book").await.assert_status(StatusCode::OK);
+    let etag = response.header("etag").expect("The response has an ETag");
+
+    match &harness.database {
+        Database::Postgres(pool) => sqlx::raw_sql("CLUSTER book USING book_name_key")
+            .execute(pool)
+            .await
+            .map(drop),
+        Database::Sqlite(pool) => sqlx::raw_sql("VACUUM").execute(pool).await.map(drop),
+    }?;
+    app.get("/v1/book")
+        .header("if-none-match", etag)
+        .await
+        .assert_status(StatusCode::NOT_MODIFIED);
+    Ok(())
+}
+
+/// A book added in the same second as the newest one, or committed after a newer one, still changes
+/// the list
+#[integration_test]
+async fn http_list_changed_in_same_second(harness: TestHarness) -> color_eyre::Result<()> {
+    let app = harness.app();
+    // Newer than the book registered below. Put in before the first request, so a server caching
+    // the list doesn't need to hear about it.
+    BookFactory::new()
+        .updated_at(Utc::now() + chrono::Duration::hours(1))
+        .insert(&harness)
+        .await?;
+
+    let response = app.get("/v1/book").await.assert_no_header("last-modified");
+    let etag = response.header("etag").expect("The response has an ETag");
+
+    register(&app).await;
+    app.get("/v1/book")
+        .header("if-none-match", etag)
+        .header(
+            "if-modified-since",
+            &httpdate::fmt_http_date(SystemTime::now()),
+        )
+        .await
+        .assert_status(StatusCode::OK);
+    Ok(())
+}
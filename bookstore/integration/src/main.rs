@@ -1,7 +1,16 @@
 use testharness::run_tests;
 
 pub mod bookstore_test;
+pub mod cache_test;
+pub mod harness_options_test;
+pub mod http_cache_test;
+pub mod migrations_test;
+pub mod openapi_test;
+pub mod query_metadata_test;
+pub mod rate_limit_test;
+pub mod replica_test;
 pub mod testharness;
+pub mod unit_of_work_test;
 
 fn main() -> color_eyre::Result<()> {
     run_tests("integration", "debug")
@@ -0,0 +1,62 @@
+use axum::http::StatusCode;
+use bookstore::{database::Database, migrations::MigrationState};
+use integration_macros::integration_test;
+
+use crate::testharness::TestHarness;
+
+#[integration_test]
+async fn migration_rollback_and_readiness(harness: TestHarness) -> color_eyre::Result<()> {
+    let database = &harness.database;
+    let app = harness.app();
+
+    let statuses = database.migration_status().await?;
+    assert!(!statuses.is_empty());
+    assert!(statuses.iter().all(|s| s.state == MigrationState::Applied));
+    app.get("/ready")
+        .await
+        .assert_status(StatusCode::NO_CONTENT);
+
+    let newest = statuses.last().unwrap().version;
+    assert_eq!(database.rollback(1).await?, vec![newest]);
+    let statuses = database.migration_status().await?;
+    assert_eq!(statuses.last().unwrap().state, MigrationState::Pending);
+    app.get("/ready")
+        .await
+        .assert_status(StatusCode::SERVICE_UNAVAILABLE);
+
+    database.run_migrations().await?;
+    app.get("/ready")
+        .await
+        .assert_status(StatusCode::NO_CONTENT);
+    Ok(())
+}
+
+#[integration_test]
+async fn migration_status_without_migrations_table(harness: TestHarness) -> color_eyre::Result<()> {
+    let database = &harness.database;
+    let applied = database.migration_status().await?.len();
+    database.rollback(applied).await?;
+    let drop_table = "DROP TABLE _sqlx_migrations";
+    match database {
+        Database::Postgres(pool) => sqlx::raw_sql(drop_table).execute(pool).await.map(drop),
+        Database::Sqlite(pool) => sqlx::raw_sql(drop_table).execute(pool).await.map(drop),
+    }?;
+
+    let statuses = database.migration_status().await?;
+    assert_eq!(statuses.len(), applied);
+    assert!(statuses.iter().all(|s| s.state == MigrationState::Pending));
+    harness
+        .app()
+        .get("/ready")
+        .await
+        .assert_status(StatusCode::SERVICE_UNAVAILABLE);
+
+    // Checking the status must not have created the table
+    let count = "SELECT count(*) FROM _sqlx_migrations";
+    let table_created = match database {
+        Database::Postgres(pool) => sqlx::raw_sql(count).execute(pool).await.is_ok(),
+        Database::Sqlite(pool) => sqlx::raw_sql(count).execute(pool).await.is_ok(),
+    };
+    assert!(!table_created);
+    Ok(())
+}
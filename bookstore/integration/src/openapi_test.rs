@@ -0,0 +1,29 @@
+use color_eyre::{
+    Section,
+    eyre::{Context, eyre},
+};
+use integration_macros::integration_test;
+
+use crate::testharness::TestHarness;
+
+const SPEC_PATH: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/openapi.json");
+
+#[integration_test(tags("in_process"))]
+async fn openapi_spec_up_to_date(_harness: TestHarness) -> color_eyre::Result<()> {
+    let generated = bookstore::openapi::openapi().to_pretty_json()? + "\n";
+    if std::env::var("UPDATE_OPENAPI_SPEC").is_ok() {
+        std::fs::write(SPEC_PATH, &generated)
+            .wrap_err_with(|| format!("Could not write {SPEC_PATH}"))?;
+        return Ok(());
+    }
+
+    let committed = std::fs::read_to_string(SPEC_PATH)
+        .wrap_err_with(|| format!("Could not read {SPEC_PATH}"))?;
+    if committed != generated {
+        return Err(eyre!(
+            "The committed OpenAPI spec at {SPEC_PATH} differs from the one generated from the handlers"
+        ))
+        .suggestion("Regenerate it with: UPDATE_OPENAPI_SPEC=1 cargo test --test integration openapi_spec");
+    }
+    Ok(())
+}
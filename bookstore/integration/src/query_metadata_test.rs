@@ -0,0 +1,57 @@
+use color_eyre::{
+    Section,
+    eyre::{Context, ensure, eyre},
+};
+use serde::Deserialize;
+use sqlx::Executor;
+
+use bookstore::database::Database;
+use integration_macros::integration_test;
+
+use crate::testharness::TestHarness;
+
+const METADATA_DIR: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/.sqlx");
+
+#[derive(Deserialize)]
+struct QueryMetadata {
+    db_name: String,
+    query: String,
+    describe: serde_json::Value,
+}
+
+/// The query macros are checked against the committed metadata, so this makes sure the metadata
+/// still describes what the migrated database would answer
+#[integration_test(tags("in_process"))]
+async fn query_metadata_up_to_date(harness: TestHarness) -> color_eyre::Result<()> {
+    let mut checked = 0;
+    for entry in std::fs::read_dir(METADATA_DIR)
+        .wrap_err_with(|| format!("Could not read {METADATA_DIR}"))?
+    {
+        let path = entry?.path();
+        let metadata: QueryMetadata = serde_json::from_slice(&std::fs::read(&path)?)
+            .wrap_err_with(|| format!("Invalid query metadata in {}", path.display()))?;
+        let describe = match &harness.database {
+            Database::Postgres(pool) if metadata.db_name == "PostgreSQL" => {
+                serde_json::to_value(pool.describe(&metadata.query).await?)?
+            }
+            Database::Sqlite(pool) if metadata.db_name == "SQLite" => {
+                serde_json::to_value(pool.describe(&metadata.query).await?)?
+            }
+            _ => continue,
+        };
+        if describe != metadata.describe {
+            return Err(eyre!(
+                "The query metadata in {} is outdated for query: {}",
+                path.display(),
+                metadata.query
+            ))
+            .suggestion("Regenerate it with ./prepare-queries.sh");
+        }
+        checked += 1;
+    }
+    ensure!(
+        checked > 0,
+        "No query metadata found for this database backend in {METADATA_DIR}"
+    );
+    Ok(())
+}
@@ -0,0 +1,200 @@
+use std::{net::SocketAddr, time::Duration};
+
+use axum::{
+    Router,
+    extract::ConnectInfo,
+    http::{Request, StatusCode, request::Builder},
+};
+use bookstore::{
+    create_router,
+    database::Database,
+    handlers::BookRegistration,
+    rate_limit::{
+        ClientResolver, InMemoryRateLimitStore, PostgresRateLimitStore, Quota, RateLimitConfig,
+        RateLimiter,
+    },
+    util::ProblemDetails,
+};
+use integration_macros::integration_test;
+use tower::ServiceExt;
+
+use crate::testharness::{TestHarness, skip};
+
+const CONFIG: RateLimitConfig = RateLimitConfig {
+    read: Quota {
+        burst: 100,
+        period: Duration::from_secs(60),
+    },
+    write: Quota {
+        burst: 2,
+        period: Duration::from_secs(60),
+    },
+};
+
+async fn register(app: &Router, api_key: &str, name: &str) -> color_eyre::Result<StatusCode> {
+    send(
+        app,
+        Request::post("/v1/book").header("x-api-key", api_key),
+        name,
+    )
+    .await
+}
+
+/// Registers the book `name` with `request`, which tells who is registering it
+async fn send(app: &Router, request: Builder, name: &str) -> color_eyre::Result<StatusCode> {
+    let body = serde_json::to_string(&BookRegistration {
+        name: name.to_string(),
+        description: String::from("A book registered too often"),
+    })?;
+    let request = request
+        .header("content-type", "application/json")
+        .body(body)?;
+    Ok(app.clone().oneshot(request).await?.status())
+}
+
+/// A request coming from `peer`, the way it gets to the router when it's served
+fn from(peer: &str) -> Builder {
+    let peer: SocketAddr = format!("{peer}:40000").parse().unwrap();
+    Request::post("/v1/book").extension(ConnectInfo(peer))
+}
+
+fn limiter() -> RateLimiter {
+    RateLimiter::new(CONFIG, InMemoryRateLimitStore::new())
+}
+
+fn clients() -> ClientResolver {
+    ClientResolver::new().with_api_keys([String::from("alice"), String::from("bob")])
+}
+
+#[integration_test(tags("in_process"))]
+async fn rate_limit(harness: TestHarness) -> color_eyre::Result<()> {
+    let state = harness.app_state().with_clients(clients());
+    let app = create_router(state.with_rate_limiter(limiter()));
+
+    assert_eq!(register(&app, "alice", "Emma").await?, StatusCode::OK);
+    assert_eq!(register(&app, "alice", "Persuasion").await?, StatusCode::OK);
+
+    let body = serde_json::to_string(&BookRegistration {
+        name: String::from("Sanditon"),
+        description: String::from("Never finished"),
+    })?;
+    let request = Request::post("/v1/book")
+        .header("content-type", "application/json")
+        .header("x-api-key", "alice")
+        .body(body)?;
+    let response = app.clone().oneshot(request).await?;
+    assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+    let headers = response.headers();
+    assert_eq!(headers["ratelimit-limit"], "2");
+    assert_eq!(headers["ratelimit-remaining"], "0");
+    assert_eq!(headers["retry-after"], "30");
+    assert_eq!(headers["ratelimit-reset"], "60");
+    let bytes = axum::body::to_bytes(response.into_body(), usize::MAX).await?;
+    let problem = serde_json::from_slice::<ProblemDetails>(&bytes)?;
+    assert_eq!(problem.status, 429);
+    assert_eq!(problem.instance.as_deref(), Some("/v1/book"));
+
+    // Other clients, and reads have quotas of their own
+    assert_eq!(register(&app, "bob", "Sanditon").await?, StatusCode::OK);
+    let request = Request::get("/v1/book")
+        .header("x-api-key", "alice")
+        .body(String::new())?;
+    let response = app.oneshot(request).await?;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.headers()["ratelimit-limit"], "100");
+    assert_eq!(response.headers()["ratelimit-remaining"], "99");
+    Ok(())
+}
+
+/// Keys the limiter doesn't know don't get buckets of their own, so making up a new key for every
+/// request doesn't get around the limit of the address
+#[integration_test(tags("in_process"))]
+async fn rate_limit_rotating_keys(harness: TestHarness) -> color_eyre::Result<()> {
+    let state = harness.app_state().with_clients(clients());
+    let app = create_router(state.with_rate_limiter(limiter()));
+    let client = |key: &str| from("203.0.113.7").header("x-api-key", key);
+
+    assert_eq!(send(&app, client("key-1"), "Emma").await?, StatusCode::OK);
+    assert_eq!(
+        send(&app, client("key-2"), "Persuasion").await?,
+        StatusCode::OK
+    );
+    assert_eq!(
+        send(&app, client("key-3"), "Sanditon").await?,
+        StatusCode::TOO_MANY_REQUESTS
+    );
+    assert_eq!(
+        send(&app, client("alice"), "Sanditon").await?,
+        StatusCode::OK
+    );
+    Ok(())
+}
+
+/// Behind a trusted proxy, clients are told apart by the address the proxy forwards for, which
+/// clients can't make up
+#[integration_test(tags("in_process"))]
+async fn rate_limit_behind_proxy(harness: TestHarness) -> color_eyre::Result<()> {
+    let clients = clients().with_trusted_proxies(["10.0.0.0/8".parse()?]);
+    let state = harness.app_state().with_clients(clients);
+    let app = create_router(state.with_rate_limiter(limiter()));
+    let forwarded = |client: &str| from("10.0.0.1").header("x-forwarded-for", client);
+
+    assert_eq!(
+        send(&app, forwarded("203.0.113.7"), "Emma").await?,
+        StatusCode::OK
+    );
+    assert_eq!(
+        send(&app, forwarded("198.51.100.1, 203.0.113.7"), "Persuasion").await?,
+        StatusCode::OK
+    );
+    assert_eq!(
+        send(&app, forwarded("198.51.100.2, 203.0.113.7"), "Sanditon").await?,
+        StatusCode::TOO_MANY_REQUESTS
+    );
+    assert_eq!(
+        send(&app, forwarded("203.0.113.8"), "Sanditon").await?,
+        StatusCode::OK
+    );
+
+    // Anyone else is only told apart by their own address
+    let direct = |client: &str| from("192.0.2.1").header("x-forwarded-for", client);
+    assert_eq!(
+        send(&app, direct("203.0.113.9"), "Mansfield Park").await?,
+        StatusCode::OK
+    );
+    assert_eq!(
+        send(&app, direct("203.0.113.10"), "Lady Susan").await?,
+        StatusCode::OK
+    );
+    assert_eq!(
+        send(&app, direct("203.0.113.11"), "The Watsons").await?,
+        StatusCode::TOO_MANY_REQUESTS
+    );
+    Ok(())
+}
+
+/// Instances sharing the buckets through postgres enforce a single quota together
+#[integration_test(tags("in_process"))]
+async fn rate_limit_shared_through_postgres(harness: TestHarness) -> color_eyre::Result<()> {
+    let Database::Postgres(pool) = &harness.database else {
+        return skip("Only postgres can share the buckets");
+    };
+    let instance = || {
+        let limiter = RateLimiter::new(CONFIG, PostgresRateLimitStore::new(pool.clone()));
+        let state = harness.app_state().with_clients(clients());
+        create_router(state.with_rate_limiter(limiter))
+    };
+    let (first, second) = (instance(), instance());
+
+    assert_eq!(register(&first, "alice", "Emma").await?, StatusCode::OK);
+    assert_eq!(
+        register(&second, "alice", "Persuasion").await?,
+        StatusCode::OK
+    );
+    assert_eq!(
+        register(&first, "alice", "Sanditon").await?,
+        StatusCode::TOO_MANY_REQUESTS
+    );
+    assert_eq!(register(&second, "bob", "Sanditon").await?, StatusCode::OK);
+    Ok(())
+}
@@ -0,0 +1,157 @@
+use std::{net::SocketAddr, sync::Arc, time::Duration};
+
+use axum::{
+    Router,
+    extract::ConnectInfo,
+    http::{Request, StatusCode, request::Builder},
+};
+use bookstore::{
+    appstate::{AppState, Book},
+    cache::{CacheOptions, CacheStats},
+    create_router,
+    database::Database,
+    handlers::BookRegistration,
+    rate_limit::ClientResolver,
+    repository::{InMemoryBookRepository, ReplicatedBookRepository},
+};
+use integration_macros::integration_test;
+use sqlx::postgres::PgPoolOptions;
+use tower::ServiceExt;
+
+use crate::testharness::TestHarness;
+
+const WRITER: &str = "203.0.113.1";
+const READER: &str = "203.0.113.2";
+
+/// A request coming from `peer`, the way it gets to the router when it's served
+fn from(peer: &str) -> Builder {
+    let peer: SocketAddr = format!("{peer}:40000").parse().unwrap();
+    Request::builder().extension(ConnectInfo(peer))
+}
+
+async fn register(app: &Router, client: Builder) -> color_eyre::Result<Book> {
+    let body = serde_json::to_string(&BookRegistration {
+        name: String::from("The Left Hand of Darkness"),
+        description: String::from("An envoy visits a planet whose people have no fixed sex"),
+    })?;
+    let request = client
+        .method("POST")
+        .uri("/v1/book")
+        .header("content-type", "application/json")
+        .body(body)?;
+    let response = app.clone().oneshot(request).await?;
+    let bytes = axum::body::to_bytes(response.into_body(), usize::MAX).await?;
+    Ok(serde_json::from_slice(&bytes)?)
+}
+
+async fn show(app: &Router, client: Builder, book: &Book) -> color_eyre::Result<StatusCode> {
+    let request = client
+        .uri(format!("/v1/book/{}", book.id))
+        .body(String::new())?;
+    Ok(app.clone().oneshot(request).await?.status())
+}
+
+/// A lagging replica, with the primary behind it, for which clients stay on the primary for
+/// 300ms after they wrote
+fn lagging_replica() -> AppState {
+    let primary = InMemoryBookRepository::new();
+    // Never catches up, like a replica lagging behind
+    let replica = InMemoryBookRepository::new();
+    let books = ReplicatedBookRepository::new(
+        Arc::new(primary.clone()),
+        Arc::new(replica),
+        Duration::from_millis(300),
+    );
+    AppState::from_parts(books, primary.transactions(), None)
+}
+
+#[integration_test(tags("in_process"))]
+async fn replica_read_your_writes(_harness: TestHarness) -> color_eyre::Result<()> {
+    let app = create_router(lagging_replica());
+
+    let book = register(&app, from(WRITER)).await?;
+    assert_eq!(show(&app, from(WRITER), &book).await?, StatusCode::OK);
+    assert_eq!(
+        show(&app, from(READER), &book).await?,
+        StatusCode::NOT_FOUND
+    );
+
+    tokio::time::sleep(Duration::from_millis(400)).await;
+    assert_eq!(
+        show(&app, from(WRITER), &book).await?,
+        StatusCode::NOT_FOUND
+    );
+    Ok(())
+}
+
+/// Behind a trusted proxy, only the client that wrote reads from the primary, not everyone coming
+/// through the proxy, nor anyone claiming to be that client
+#[integration_test(tags("in_process"))]
+async fn replica_read_your_writes_behind_proxy(_harness: TestHarness) -> color_eyre::Result<()> {
+    let clients = ClientResolver::new().with_trusted_proxies(["10.0.0.0/8".parse()?]);
+    let app = create_router(lagging_replica().with_clients(clients));
+    let forwarded = |client: &str| from("10.0.0.1").header("x-forwarded-for", client);
+
+    let book = register(&app, forwarded(WRITER)).await?;
+    assert_eq!(show(&app, forwarded(WRITER), &book).await?, StatusCode::OK);
+    assert_eq!(
+        show(&app, forwarded(READER), &book).await?,
+        StatusCode::NOT_FOUND
+    );
+    let impostor = from(READER)
+        .header("x-forwarded-for", WRITER)
+        .header("x-client-id", WRITER);
+    assert_eq!(show(&app, impostor, &book).await?, StatusCode::NOT_FOUND);
+    Ok(())
+}
+
+#[integration_test(tags("in_process"))]
+async fn replica_unavailable_falls_back_to_primary(
+    _harness: TestHarness,
+) -> color_eyre::Result<()> {
+    let unreachable = PgPoolOptions::new()
+        .acquire_timeout(Duration::from_secs(1))
+        .connect_lazy("postgres://bookstore@127.0.0.1:1/bookstore")?;
+    let state = AppState::in_memory().with_replica(Database::Postgres(unreachable), Duration::ZERO);
+    let app = create_router(state);
+
+    let book = register(&app, from(WRITER)).await?;
+    assert_eq!(show(&app, from(READER), &book).await?, StatusCode::OK);
+    Ok(())
+}
+
+#[integration_test(tags("in_process"))]
+async fn replica_and_cache_in_any_order(harness: TestHarness) -> color_eyre::Result<()> {
+    let replica = || harness.database.clone();
+    // The replica is the primary itself, so it never lags behind
+    let options = CacheOptions {
+        replica_lag: Duration::ZERO,
+        ..CacheOptions::default()
+    };
+    let states = [
+        harness
+            .app_state()
+            .with_cache(options)
+            .with_replica(replica(), Duration::ZERO),
+        harness
+            .app_state()
+            .with_replica(replica(), Duration::ZERO)
+            .with_cache(options),
+    ];
+    for (i, state) in states.iter().enumerate() {
+        state
+            .books
+            .register_book(&BookRegistration {
+                name: format!("Solaris {i}"),
+                description: String::from("A planet covered by an ocean that seems to think"),
+            })
+            .await?;
+        // Read from the replica, through the cache
+        state.books.list_books().await?;
+        state.books.list_books().await?;
+        assert!(state.replica.is_some());
+        let cache = state.cache.as_ref().unwrap();
+        assert_eq!(cache.stats(), CacheStats { hits: 1, misses: 1 });
+    }
+    Ok(())
+}
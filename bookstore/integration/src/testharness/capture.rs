@@ -0,0 +1,112 @@
+use std::{
+    io::{self, Write},
+    sync::{Arc, Mutex},
+};
+
+use tracing::{Span, span, subscriber::Subscriber};
+use tracing_subscriber::{Layer, Registry, fmt::MakeWriter, layer::Context, registry::LookupSpan};
+
+/// Name of the span every test runs in, which collects the logs of the test
+pub const TEST_SPAN: &str = "test_case";
+
+/// The logs of a single test, collected while it runs, so they can be printed together
+#[derive(Clone, Debug, Default)]
+pub struct CapturedLogs(Arc<Mutex<Vec<u8>>>);
+
+impl CapturedLogs {
+    /// The logs of the test running in `span`, which is a span named [`TEST_SPAN`]
+    pub fn of(span: &Span) -> Option<Self> {
+        span.with_subscriber(|(id, dispatch)| {
+            let registry = dispatch.downcast_ref::<Registry>()?;
+            registry
+                .span(id)?
+                .extensions()
+                .get::<CapturedLogs>()
+                .cloned()
+        })
+        .flatten()
+    }
+
+    /// Everything logged so far, leaving the logs empty
+    pub fn take(&self) -> String {
+        let bytes = std::mem::take(&mut *self.0.lock().unwrap());
+        String::from_utf8_lossy(&bytes).into_owned()
+    }
+
+    /// The logs of the test the current span belongs to, if it belongs to any
+    fn current() -> Option<Self> {
+        Span::current()
+            .with_subscriber(|(id, dispatch)| {
+                let registry = dispatch.downcast_ref::<Registry>()?;
+                registry
+                    .span(id)?
+                    .scope()
+                    .find_map(|span| span.extensions().get::<CapturedLogs>().cloned())
+            })
+            .flatten()
+    }
+}
+
+impl Write for CapturedLogs {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.lock().unwrap().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Gives every test span logs of its own to collect into
+pub struct CaptureLayer;
+
+impl<S> Layer<S> for CaptureLayer
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    fn on_new_span(&self, attrs: &span::Attributes<'_>, id: &span::Id, ctx: Context<'_, S>) {
+        if attrs.metadata().name() != TEST_SPAN {
+            return;
+        }
+        if let Some(span) = ctx.span(id) {
+            span.extensions_mut().insert(CapturedLogs::default());
+        }
+    }
+}
+
+/// Writes the logs of a test into its captured logs, and everything logged outside of tests, like
+/// by tasks spawned without the span of the test, to stderr, so stdout only has the report
+pub struct CaptureWriter;
+
+pub enum CaptureTarget {
+    Test(CapturedLogs),
+    Stderr(io::Stderr),
+}
+
+impl Write for CaptureTarget {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            CaptureTarget::Test(logs) => logs.write(buf),
+            CaptureTarget::Stderr(stderr) => stderr.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            CaptureTarget::Test(logs) => logs.flush(),
+            CaptureTarget::Stderr(stderr) => stderr.flush(),
+        }
+    }
+}
+
+impl<'a> MakeWriter<'a> for CaptureWriter {
+    type Writer = CaptureTarget;
+
+    fn make_writer(&'a self) -> Self::Writer {
+        match CapturedLogs::current() {
+            Some(logs) => CaptureTarget::Test(logs),
+            None => CaptureTarget::Stderr(io::stderr()),
+        }
+    }
+}
@@ -0,0 +1,314 @@
+use std::{
+    fmt::Debug,
+    future::{Future, IntoFuture},
+    pin::Pin,
+};
+
+use axum::{
+    Router,
+    body::{Body, Bytes},
+    http::{HeaderMap, HeaderName, HeaderValue, Method, Request, StatusCode, header},
+};
+use serde::{Serialize, de::DeserializeOwned};
+use similar::TextDiff;
+use tower::ServiceExt;
+use tracing::debug;
+
+/// Sends requests to the application, in process or to a server over HTTP. Made by
+/// [`TestHarness::app`](super::TestHarness::app), or by [`TestClient::new`] for a router with a
+/// state of its own.
+#[derive(Clone)]
+pub struct TestClient {
+    target: Target,
+    headers: HeaderMap,
+}
+
+#[derive(Clone)]
+enum Target {
+    Router(Router),
+    Http {
+        client: reqwest::Client,
+        url: String,
+    },
+}
+
+impl TestClient {
+    /// Sends the requests straight to `router`, without a server in between
+    pub fn new(router: Router) -> Self {
+        Self {
+            target: Target::Router(router),
+            headers: HeaderMap::new(),
+        }
+    }
+
+    /// Sends the requests over HTTP to the server at `url`
+    pub fn http(url: &str) -> Self {
+        let client = reqwest::Client::builder()
+            // The router doesn't follow redirects either
+            .redirect(reqwest::redirect::Policy::none())
+            .build()
+            .expect("The HTTP client can be built");
+        Self {
+            target: Target::Http {
+                client,
+                url: url.to_string(),
+            },
+            headers: HeaderMap::new(),
+        }
+    }
+
+    /// Sends the header with every request of this client
+    pub fn with_header(mut self, name: &'static str, value: &str) -> Self {
+        self.headers
+            .insert(HeaderName::from_static(name), header_value(value));
+        self
+    }
+
+    pub fn request(&self, method: Method, path: &str) -> TestRequest {
+        TestRequest {
+            target: self.target.clone(),
+            method,
+            path: path.to_string(),
+            headers: self.headers.clone(),
+            body: Bytes::new(),
+        }
+    }
+
+    pub fn get(&self, path: &str) -> TestRequest {
+        self.request(Method::GET, path)
+    }
+
+    pub fn post(&self, path: &str) -> TestRequest {
+        self.request(Method::POST, path)
+    }
+
+    pub fn post_json<T: Serialize + ?Sized>(&self, path: &str, body: &T) -> TestRequest {
+        self.post(path).json(body)
+    }
+}
+
+/// A request of a [`TestClient`], sent by awaiting it
+pub struct TestRequest {
+    target: Target,
+    method: Method,
+    path: String,
+    headers: HeaderMap,
+    body: Bytes,
+}
+
+impl TestRequest {
+    pub fn header(mut self, name: &'static str, value: &str) -> Self {
+        self.headers
+            .insert(HeaderName::from_static(name), header_value(value));
+        self
+    }
+
+    /// Sends `body` as JSON
+    pub fn json<T: Serialize + ?Sized>(mut self, body: &T) -> Self {
+        let body = serde_json::to_vec(body).expect("The request body can't be serialized to JSON");
+        self.headers.insert(
+            header::CONTENT_TYPE,
+            HeaderValue::from_static("application/json"),
+        );
+        self.body = Bytes::from(body);
+        self
+    }
+
+    /// Sends `body` as is, for the requests a client wouldn't serialize itself
+    pub fn body(mut self, body: impl Into<Bytes>) -> Self {
+        self.body = body.into();
+        self
+    }
+
+    async fn send(self) -> TestResponse {
+        let (status, headers, body) = match self.target {
+            Target::Router(router) => {
+                let mut request = Request::builder()
+                    .method(self.method.clone())
+                    .uri(&self.path)
+                    .body(Body::from(self.body))
+                    .unwrap_or_else(|e| {
+                        panic!("Invalid request {} {}: {e}", self.method, self.path)
+                    });
+                *request.headers_mut() = self.headers;
+                let response = match router.oneshot(request).await {
+                    Ok(response) => response,
+                    Err(infallible) => match infallible {},
+                };
+                let (parts, body) = response.into_parts();
+                let body = axum::body::to_bytes(body, usize::MAX)
+                    .await
+                    .unwrap_or_else(|e| {
+                        panic!("Could not read the response of {}: {e}", self.path)
+                    });
+                (parts.status, parts.headers, body)
+            }
+            Target::Http { client, url } => {
+                let response = client
+                    .request(self.method.clone(), format!("{url}{}", self.path))
+                    .headers(self.headers)
+                    .body(self.body)
+                    .send()
+                    .await
+                    .unwrap_or_else(|e| {
+                        panic!("Could not send {} {}: {e}", self.method, self.path)
+                    });
+                let status = response.status();
+                let headers = response.headers().clone();
+                let body = response.bytes().await.unwrap_or_else(|e| {
+                    panic!("Could not read the response of {}: {e}", self.path)
+                });
+                (status, headers, body)
+            }
+        };
+        let request_id = headers
+            .get("x-request-id")
+            .and_then(|id| id.to_str().ok())
+            .map(String::from);
+        debug!(
+            request_id,
+            status = status.as_u16(),
+            "{} {}",
+            self.method,
+            self.path
+        );
+        TestResponse {
+            request: format!("{} {}", self.method, self.path),
+            request_id,
+            status,
+            headers,
+            body,
+        }
+    }
+}
+
+impl IntoFuture for TestRequest {
+    type Output = TestResponse;
+    type IntoFuture = Pin<Box<dyn Future<Output = TestResponse> + Send>>;
+
+    fn into_future(self) -> Self::IntoFuture {
+        Box::pin(self.send())
+    }
+}
+
+/// The response to a [`TestRequest`], with its whole body read. The assertions panic with the
+/// request, its request id to find its logs by, and what differs from the expectation.
+pub struct TestResponse {
+    request: String,
+    request_id: Option<String>,
+    status: StatusCode,
+    headers: HeaderMap,
+    body: Bytes,
+}
+
+impl TestResponse {
+    pub fn status(&self) -> StatusCode {
+        self.status
+    }
+
+    pub fn headers(&self) -> &HeaderMap {
+        &self.headers
+    }
+
+    /// Value of the header, None if the response has none or it isn't text
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers.get(name).and_then(|value| value.to_str().ok())
+    }
+
+    /// Id the application gave the request, which its logs are tagged with
+    pub fn request_id(&self) -> Option<&str> {
+        self.request_id.as_deref()
+    }
+
+    pub fn bytes(&self) -> &Bytes {
+        &self.body
+    }
+
+    pub fn text(&self) -> String {
+        String::from_utf8_lossy(&self.body).into_owned()
+    }
+
+    /// Deserializes the body as JSON
+    #[track_caller]
+    pub fn json<T: DeserializeOwned>(&self) -> T {
+        serde_json::from_slice(&self.body).unwrap_or_else(|e| {
+            self.fail(format!(
+                "Could not deserialize the body into {}: {e}",
+                std::any::type_name::<T>()
+            ))
+        })
+    }
+
+    #[track_caller]
+    pub fn assert_status<S>(self, expected: S) -> Self
+    where
+        S: TryInto<StatusCode>,
+        S::Error: Debug,
+    {
+        let expected = expected.try_into().expect("Invalid expected status");
+        if self.status != expected {
+            self.fail(format!("Expected status {expected}, got {}", self.status));
+        }
+        self
+    }
+
+    #[track_caller]
+    pub fn assert_header(self, name: &str, expected: &str) -> Self {
+        match self.header(name) {
+            Some(value) if value == expected => {}
+            Some(value) => self.fail(format!(
+                "Expected header {name}: {expected}, got {name}: {value}"
+            )),
+            None => self.fail(format!("Expected header {name}: {expected}, got none")),
+        }
+        self
+    }
+
+    #[track_caller]
+    pub fn assert_no_header(self, name: &str) -> Self {
+        if let Some(value) = self.header(name) {
+            self.fail(format!("Expected no header {name}, got {name}: {value}"));
+        }
+        self
+    }
+
+    /// Compares the body with `expected` as JSON, so formatting and the order of fields don't
+    /// matter. Shows the lines that differ otherwise.
+    #[track_caller]
+    pub fn assert_json<T: Serialize + ?Sized>(self, expected: &T) -> Self {
+        let expected = serde_json::to_value(expected).expect("The expected body isn't JSON");
+        let actual: serde_json::Value = self.json();
+        if actual != expected {
+            let expected = pretty(&expected);
+            let actual = pretty(&actual);
+            let diff = TextDiff::from_lines(&expected, &actual)
+                .unified_diff()
+                .header("expected", "actual")
+                .to_string();
+            self.fail(format!("The body differs from the expected one\n{diff}"));
+        }
+        self
+    }
+
+    #[track_caller]
+    fn fail(&self, message: String) -> ! {
+        let body = match serde_json::from_slice::<serde_json::Value>(&self.body) {
+            Ok(json) => pretty(&json),
+            Err(_) => self.text(),
+        };
+        panic!(
+            "{message}\nrequest: {} (request id {})\nresponse body:\n{body}",
+            self.request,
+            self.request_id.as_deref().unwrap_or("unknown"),
+        );
+    }
+}
+
+fn header_value(value: &str) -> HeaderValue {
+    HeaderValue::from_str(value).unwrap_or_else(|e| panic!("Invalid header value {value:?}: {e}"))
+}
+
+fn pretty(json: &serde_json::Value) -> String {
+    // safety: a value can always be serialized
+    serde_json::to_string_pretty(json).unwrap() + "\n"
+}
@@ -1,10 +1,304 @@
-use std::time::Duration;
+use std::{
+    fs::File,
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
 
+use bookstore::{
+    database::Database,
+    migrations::{POSTGRES_MIGRATOR, SQLITE_MIGRATOR},
+};
 use color_eyre::{Help, eyre::Context};
-use sqlx::{PgPool, Pool, Postgres, migrate, postgres::PgPoolOptions, query};
-use tracing::{debug, log::warn};
+use sha2::{Digest, Sha256};
+use sqlx::{
+    PgPool, Pool, Postgres, SqlitePool,
+    migrate::Migrator,
+    postgres::PgPoolOptions,
+    query,
+    sqlite::{SqliteConnectOptions, SqliteJournalMode, SqlitePoolOptions},
+};
+use tracing::{debug, warn};
 use uuid::Uuid;
 
+/// Every test database is named with this prefix, followed by a uuid and the name of the test, so
+/// leftovers can be told apart from other databases. The uuid tells when it was created.
+const TEST_DATABASE_PREFIX: &str = "itest_";
+
+/// Test databases older than this, and templates of other migrations unused for this long, are
+/// left behind by earlier runs, rather than in use by one running at the same time
+const STALE_AFTER: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// The migrated database every test database is copied from is named with this prefix, followed by
+/// the hash of the migrations. It outlives the run, so later runs with the same migrations skip
+/// migrating altogether.
+const TEMPLATE_PREFIX: &str = "itemplate_";
+
+/// Serializes building the template between runs started at the same time on one postgres server
+const TEMPLATE_LOCK: i64 = 0x6974_656d_706c;
+
+/// Hands out a fresh database to every test. Which backend is used depends on the scheme of
+/// TEST_DATABASE_URL: `sqlite:<directory>` puts a database file per test into the directory (the
+/// system temp directory if empty), anything else is treated as a postgres server.
+///
+/// The migrations run only once, on a template, which every test database is a copy of.
+#[derive(Clone, Debug)]
+pub enum DatabaseProvider {
+    Postgres {
+        admin: AdminDatabaseConnection,
+        template: String,
+    },
+    Sqlite {
+        directory: PathBuf,
+        template: PathBuf,
+    },
+}
+
+impl DatabaseProvider {
+    pub async fn from_env() -> color_eyre::Result<Self> {
+        let url = std::env::var("TEST_DATABASE_URL").unwrap_or_default();
+        if let Some(directory) = url.strip_prefix("sqlite:") {
+            let directory = if directory.is_empty() {
+                std::env::temp_dir()
+            } else {
+                PathBuf::from(directory)
+            };
+            let template = create_sqlite_template(&directory).await?;
+            Ok(DatabaseProvider::Sqlite {
+                directory,
+                template,
+            })
+        } else {
+            let admin = create_administrative_database().await;
+            let template = admin.create_template().await?;
+            Ok(DatabaseProvider::Postgres { admin, template })
+        }
+    }
+
+    pub async fn create_test_database(&self, test_name: &str) -> color_eyre::Result<Database> {
+        match self {
+            DatabaseProvider::Postgres { admin, template } => admin
+                .create_application_pool(test_name, template)
+                .await
+                .map(Database::Postgres),
+            DatabaseProvider::Sqlite {
+                directory,
+                template,
+            } => create_sqlite_pool(directory, template, test_name)
+                .await
+                .map(Database::Sqlite),
+        }
+    }
+
+    /// Where the database of a test can be found, for inspecting it after the test failed
+    pub fn location(&self, database: &Database) -> String {
+        match database {
+            Database::Postgres(pool) => {
+                connect_with_dbname(pool.connect_options().get_database().unwrap_or_default())
+            }
+            Database::Sqlite(pool) => pool.connect_options().get_filename().display().to_string(),
+        }
+    }
+
+    /// Url to connect to the test database with
+    pub fn url(&self, database: &Database) -> String {
+        match database {
+            Database::Postgres(_) => self.location(database),
+            Database::Sqlite(_) => format!("sqlite:{}", self.location(database)),
+        }
+    }
+
+    pub async fn drop_test_database(&self, database: Database) {
+        match (self, database) {
+            (DatabaseProvider::Postgres { admin, .. }, Database::Postgres(pool)) => {
+                admin.drop_application_pool(pool).await
+            }
+            (DatabaseProvider::Sqlite { .. }, Database::Sqlite(pool)) => {
+                drop_sqlite_pool(pool).await
+            }
+            _ => unreachable!("Test databases always come from the provider of the run"),
+        }
+    }
+
+    /// Removes the test databases left behind by earlier runs, and the templates of other
+    /// migrations, returning how many there were. Only what is older than [`STALE_AFTER`] goes,
+    /// so runs at the same time keep theirs, and templates still being built are never touched.
+    pub async fn purge_stale(&self) -> color_eyre::Result<usize> {
+        match self {
+            DatabaseProvider::Postgres { admin, template } => admin.purge_stale(template).await,
+            DatabaseProvider::Sqlite {
+                directory,
+                template,
+            } => purge_stale_sqlite(directory, template),
+        }
+    }
+}
+
+/// Name of the template for the migrations of `migrator`. Covers everything sqlx checks about an
+/// applied migration, so a template is never reused after a migration was added or edited.
+fn template_name(migrator: &Migrator) -> String {
+    let mut hasher = Sha256::new();
+    for migration in migrator.iter() {
+        hasher.update(migration.version.to_le_bytes());
+        hasher.update(migration.description.as_bytes());
+        hasher.update([u8::from(migration.migration_type.is_down_migration())]);
+        hasher.update(&migration.checksum);
+    }
+    let hash = hasher.finalize();
+    let hex: String = hash[..8].iter().map(|byte| format!("{byte:02x}")).collect();
+    format!("{TEMPLATE_PREFIX}{hex}")
+}
+
+/// Name of a new test database, which postgres cuts at 63 bytes, so it is cut at the last whole
+/// character before that. Only the function of the test is part of it, its module wouldn't leave
+/// enough room.
+fn test_database_name(test_name: &str) -> String {
+    let function = test_name.rsplit("::").next().unwrap_or(test_name);
+    let mut name = format!(
+        "{TEST_DATABASE_PREFIX}{}_{function}",
+        Uuid::now_v7().simple()
+    );
+    let mut end = name.len().min(63);
+    while !name.is_char_boundary(end) {
+        end -= 1;
+    }
+    name.truncate(end);
+    name
+}
+
+/// Whether the test database `name` was created more than [`STALE_AFTER`] ago. Databases named
+/// without the time they were created are kept, since there is no telling.
+fn is_stale_test_database(name: &str) -> bool {
+    let created = name
+        .strip_prefix(TEST_DATABASE_PREFIX)
+        .and_then(|rest| rest.get(..32))
+        .and_then(|uuid| Uuid::try_parse(uuid).ok())
+        .and_then(|uuid| uuid.get_timestamp())
+        .map(|timestamp| {
+            let (seconds, nanos) = timestamp.to_unix();
+            UNIX_EPOCH + Duration::new(seconds, nanos)
+        });
+    created.is_some_and(is_stale)
+}
+
+fn is_stale(time: SystemTime) -> bool {
+    time.elapsed().is_ok_and(|age| age > STALE_AFTER)
+}
+
+/// Migrates the template file, unless an earlier run left one for the same migrations
+async fn create_sqlite_template(directory: &Path) -> color_eyre::Result<PathBuf> {
+    let name = template_name(&SQLITE_MIGRATOR);
+    let template = directory.join(format!("{name}.db"));
+    if template.exists() {
+        debug!(db = %template.display(), "Reusing the template database");
+        // The modification time tells when it was last used, for purging
+        File::options()
+            .append(true)
+            .open(&template)
+            .and_then(|file| file.set_modified(SystemTime::now()))
+            .wrap_err("Could not mark the template database as used")?;
+        return Ok(template);
+    }
+
+    // Built under a name of its own and moved into place once migrated, so a run that is
+    // interrupted, or another run building it too, never leaves a half migrated template behind
+    let building = directory.join(format!("{name}-{}.db.building", Uuid::new_v4().simple()));
+    debug!(db = %template.display(), "Creating the template database");
+    let options = SqliteConnectOptions::new()
+        .filename(&building)
+        .create_if_missing(true)
+        .journal_mode(SqliteJournalMode::Delete);
+    let pool = SqlitePoolOptions::new()
+        .max_connections(1)
+        .connect_with(options)
+        .await?;
+    let migrated = SQLITE_MIGRATOR.run(&pool).await;
+    pool.close().await;
+    if let Err(e) = migrated {
+        let _ = std::fs::remove_file(&building);
+        return Err(e).wrap_err("Could not migrate the template database");
+    }
+    std::fs::rename(&building, &template)
+        .wrap_err_with(|| format!("Could not move the template to {}", template.display()))?;
+    Ok(template)
+}
+
+async fn create_sqlite_pool(
+    directory: &Path,
+    template: &Path,
+    test_name: &str,
+) -> color_eyre::Result<SqlitePool> {
+    let path = directory.join(format!("{}.db", test_database_name(test_name)));
+    debug!(db = %path.display(), "Creating database");
+    std::fs::copy(template, &path).wrap_err_with(|| {
+        format!(
+            "Could not copy the template {} to {}",
+            template.display(),
+            path.display()
+        )
+    })?;
+    let options = SqliteConnectOptions::new()
+        .filename(&path)
+        .journal_mode(SqliteJournalMode::Wal);
+    SqlitePoolOptions::new()
+        .max_connections(4)
+        .connect_with(options)
+        .await
+        .wrap_err_with(|| format!("Could not open the database {}", path.display()))
+}
+
+async fn drop_sqlite_pool(pool: SqlitePool) {
+    let path = pool.connect_options().get_filename().to_path_buf();
+    pool.close().await;
+    debug!(db = %path.display(), "Removing database");
+    for suffix in ["", "-wal", "-shm"] {
+        let mut file = path.clone().into_os_string();
+        file.push(suffix);
+        match std::fs::remove_file(&file) {
+            Ok(()) => {}
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+            Err(e) => warn!(
+                "Could not delete database file '{}', it's lingering now: {e}",
+                PathBuf::from(file).display()
+            ),
+        }
+    }
+}
+
+fn purge_stale_sqlite(directory: &Path, template: &Path) -> color_eyre::Result<usize> {
+    let mut purged = 0;
+    for entry in std::fs::read_dir(directory)
+        .wrap_err_with(|| format!("Could not read {}", directory.display()))?
+    {
+        let entry = entry?;
+        let path = entry.path();
+        let Some(name) = path.file_name().and_then(|name| name.to_str()) else {
+            continue;
+        };
+        // The database file itself, or its -wal and -shm files, but never a template being built
+        let Some(database) = name.split(".db").next().filter(|_| {
+            name.ends_with(".db") || name.ends_with(".db-wal") || name.ends_with(".db-shm")
+        }) else {
+            continue;
+        };
+        let stale = if database.starts_with(TEST_DATABASE_PREFIX) {
+            is_stale_test_database(database)
+        } else if database.starts_with(TEMPLATE_PREFIX) && path != template {
+            entry.metadata()?.modified().is_ok_and(is_stale)
+        } else {
+            false
+        };
+        if !stale {
+            continue;
+        }
+        std::fs::remove_file(&path)
+            .wrap_err_with(|| format!("Could not delete {}", path.display()))?;
+        if name.ends_with(".db") {
+            purged += 1;
+        }
+    }
+    Ok(purged)
+}
+
 #[derive(Clone, Debug)]
 pub struct AdminDatabaseConnection {
     pool: Pool<Postgres>,
@@ -31,7 +325,8 @@ impl AdminDatabaseConnection {
         drop(app_pool);
         debug!("Dropping database");
         let mut executor = self.pool.acquire().await.unwrap();
-        if let Err(e) = query(&format!("DROP DATABASE {dbname}"))
+        // FORCE disconnects whatever the test left running in the background, like listeners
+        if let Err(e) = query(&format!("DROP DATABASE {dbname} WITH (FORCE)"))
             .execute(&mut *executor)
             .await
         {
@@ -42,29 +337,142 @@ impl AdminDatabaseConnection {
         }
     }
 
-    pub async fn create_application_pool(&self, test_name: &str) -> Result<PgPool, sqlx::Error> {
-        let name = format!("{test_name}-{}", Uuid::new_v4().hyphenated()).replace('-', "_");
+    /// Migrates the template database, unless an earlier run left one for the same migrations,
+    /// returning its name
+    async fn create_template(&self) -> color_eyre::Result<String> {
+        let name = template_name(&POSTGRES_MIGRATOR);
+        // Advisory locks belong to the connection, so it has to be the same one for unlocking
+        let mut lock = self.pool.acquire().await?;
+        query("SELECT pg_advisory_lock($1)")
+            .bind(TEMPLATE_LOCK)
+            .execute(&mut *lock)
+            .await?;
+        let created = self.create_template_locked(&name).await;
+        query("SELECT pg_advisory_unlock($1)")
+            .bind(TEMPLATE_LOCK)
+            .execute(&mut *lock)
+            .await?;
+        created.wrap_err("Could not create the template database")?;
+        Ok(name)
+    }
+
+    async fn create_template_locked(&self, name: &str) -> Result<(), sqlx::Error> {
+        let exists: bool =
+            sqlx::query_scalar("SELECT EXISTS (SELECT FROM pg_database WHERE datname = $1)")
+                .bind(name)
+                .fetch_one(&self.pool)
+                .await?;
+        if exists {
+            debug!(db = name, "Reusing the template database");
+            return mark_used(&self.pool, name).await;
+        }
+
+        // Built under a name of its own and renamed once migrated, so an interrupted run never
+        // leaves a half migrated template behind
+        let building = format!("{name}_building");
+        debug!(db = name, "Creating the template database");
+        query(&format!("DROP DATABASE IF EXISTS {building} WITH (FORCE)"))
+            .execute(&self.pool)
+            .await?;
+        query(&format!("CREATE DATABASE {building}"))
+            .execute(&self.pool)
+            .await?;
+        let pool = PgPoolOptions::new()
+            .max_connections(1)
+            .connect(&connect_with_dbname(&building))
+            .await?;
+        let migrated = POSTGRES_MIGRATOR.run(&pool).await;
+        // Copying a database fails while anyone is connected to it
+        pool.close().await;
+        migrated?;
+        query(&format!("ALTER DATABASE {building} RENAME TO {name}"))
+            .execute(&self.pool)
+            .await?;
+        mark_used(&self.pool, name).await
+    }
+
+    pub async fn create_application_pool(
+        &self,
+        test_name: &str,
+        template: &str,
+    ) -> color_eyre::Result<PgPool> {
+        let name = test_database_name(test_name);
         debug!(db = name, "Creating database");
-        let mut executor = self.pool.acquire().await.unwrap();
-        query(&format!("CREATE DATABASE {name}"))
+        let mut executor = self
+            .pool
+            .acquire()
+            .await
+            .wrap_err("Could not get an administrative connection")?;
+        query(&format!("CREATE DATABASE {name} TEMPLATE {template}"))
             .execute(&mut *executor)
             .await
-            .expect("Could not create appication pool");
+            .wrap_err_with(|| format!("Could not create {name} from the template {template}"))?;
 
         debug!(db = name, "Created");
 
-        let pool = PgPoolOptions::new()
+        PgPoolOptions::new()
             .min_connections(1)
             .max_connections(4)
             .connect(&connect_with_dbname(&name))
-            .await?;
+            .await
+            .wrap_err_with(|| format!("Could not connect to the database {name}"))
+    }
 
-        debug!(db = name, "Running migrations");
+    async fn purge_stale(&self, template: &str) -> color_eyre::Result<usize> {
+        let databases: Vec<(String, Option<String>)> = sqlx::query_as(
+            "SELECT datname, shobj_description(oid, 'pg_database') FROM pg_database
+            WHERE starts_with(datname, $1)
+            OR (starts_with(datname, $2) AND datname <> $3 AND datname NOT LIKE '%\\_building')",
+        )
+        .bind(TEST_DATABASE_PREFIX)
+        .bind(TEMPLATE_PREFIX)
+        .bind(template)
+        .fetch_all(&self.pool)
+        .await?;
+        let mut purged = 0;
+        for (name, comment) in &databases {
+            let stale = if name.starts_with(TEST_DATABASE_PREFIX) {
+                is_stale_test_database(name)
+            } else {
+                // Templates built before they were marked count as unused since forever
+                let used = comment
+                    .as_deref()
+                    .and_then(|comment| comment.strip_prefix(USED_AT))
+                    .and_then(|seconds| seconds.parse().ok())
+                    .map_or(UNIX_EPOCH, |seconds| {
+                        UNIX_EPOCH + Duration::from_secs(seconds)
+                    });
+                is_stale(used)
+            };
+            if !stale {
+                continue;
+            }
+            debug!(db = name, "Dropping stale database");
+            query(&format!("DROP DATABASE {name} WITH (FORCE)"))
+                .execute(&self.pool)
+                .await
+                .wrap_err_with(|| format!("Could not drop stale database {name}"))?;
+            purged += 1;
+        }
+        Ok(purged)
+    }
+}
 
-        migrate!("./migrations").run(&pool).await?;
+/// Start of the comment on a template, followed by the unix time it was last used at
+const USED_AT: &str = "used at ";
 
-        Ok(pool)
-    }
+/// Tells when the template `name` was last used in its comment, for purging
+async fn mark_used(pool: &PgPool, name: &str) -> Result<(), sqlx::Error> {
+    let seconds = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+    query(&format!(
+        "COMMENT ON DATABASE {name} IS '{USED_AT}{seconds}'"
+    ))
+    .execute(pool)
+    .await?;
+    Ok(())
 }
 
 pub async fn create_administrative_database() -> AdminDatabaseConnection {
@@ -0,0 +1,129 @@
+use bookstore::{appstate::Book, database::Database};
+use chrono::{DateTime, SecondsFormat, Utc};
+use color_eyre::eyre::Context;
+use rand::{Rng, seq::IndexedRandom};
+use serde::Deserialize;
+use uuid::Uuid;
+
+use super::TestHarness;
+
+const WORDS: &[&str] = &[
+    "amber", "atlas", "bridge", "candle", "cellar", "comet", "delta", "ember", "falcon", "garden",
+    "harbor", "island", "lantern", "meadow", "mirror", "orchard", "river", "shadow", "tower",
+    "winter",
+];
+
+/// Builds books to put into the database of a test directly, without the API. Every field that
+/// isn't given gets a random value, the name one that no other book has.
+///
+///     let book = BookFactory::new().name("Ship of Theseus").insert(&harness).await?;
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct BookFactory {
+    id: Option<Uuid>,
+    name: Option<String>,
+    description: Option<String>,
+    updated_at: Option<DateTime<Utc>>,
+}
+
+impl BookFactory {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn id(mut self, id: Uuid) -> Self {
+        self.id = Some(id);
+        self
+    }
+
+    pub fn name(mut self, name: impl Into<String>) -> Self {
+        self.name = Some(name.into());
+        self
+    }
+
+    pub fn description(mut self, description: impl Into<String>) -> Self {
+        self.description = Some(description.into());
+        self
+    }
+
+    /// SQLite keeps the milliseconds of it only
+    pub fn updated_at(mut self, updated_at: DateTime<Utc>) -> Self {
+        self.updated_at = Some(updated_at);
+        self
+    }
+
+    /// The book, with random values for the fields that weren't given, without inserting it
+    pub fn build(&self) -> Book {
+        let mut rng = rand::rng();
+        let description_length = rng.random_range(5..20);
+        let mut words = |count: usize| {
+            (0..count)
+                .map(|_| *WORDS.choose(&mut rng).expect("There are words"))
+                .collect::<Vec<_>>()
+                .join(" ")
+        };
+        Book {
+            id: self.id.unwrap_or_else(Uuid::new_v4),
+            name: self.name.clone().unwrap_or_else(|| {
+                let suffix = Uuid::new_v4().simple().to_string();
+                format!("The {} {}", words(2), &suffix[..8])
+            }),
+            description: self
+                .description
+                .clone()
+                .unwrap_or_else(|| words(description_length)),
+            // In milliseconds, the precision SQLite keeps
+            updated_at: self.updated_at.unwrap_or_else(|| {
+                DateTime::from_timestamp_millis(Utc::now().timestamp_millis())
+                    .expect("The current time is in range")
+            }),
+        }
+    }
+
+    pub async fn insert(&self, harness: &TestHarness) -> color_eyre::Result<Book> {
+        let book = self.build();
+        insert_book(&harness.database, &book).await?;
+        Ok(book)
+    }
+
+    /// Inserts `count` books built by this factory, so it shouldn't be given a name
+    pub async fn insert_many(
+        &self,
+        count: usize,
+        harness: &TestHarness,
+    ) -> color_eyre::Result<Vec<Book>> {
+        let mut books = Vec::with_capacity(count);
+        for _ in 0..count {
+            books.push(self.insert(harness).await?);
+        }
+        Ok(books)
+    }
+}
+
+async fn insert_book(database: &Database, book: &Book) -> color_eyre::Result<()> {
+    match database {
+        Database::Postgres(pool) => sqlx::query(
+            "INSERT INTO book (id, name, description, updated_at) VALUES ($1, $2, $3, $4)",
+        )
+        .bind(book.id)
+        .bind(&book.name)
+        .bind(&book.description)
+        .bind(book.updated_at)
+        .execute(pool)
+        .await
+        .map(drop),
+        Database::Sqlite(pool) => {
+            // Written the way the default of the column writes it, so the timestamps sort as text
+            sqlx::query("INSERT INTO book (id, name, description, updated_at) VALUES (?, ?, ?, ?)")
+                .bind(book.id)
+                .bind(&book.name)
+                .bind(&book.description)
+                .bind(book.updated_at.to_rfc3339_opts(SecondsFormat::Millis, true))
+                .execute(pool)
+                .await
+                .map(drop)
+        }
+    }
+    .wrap_err_with(|| format!("Could not insert the book {}", book.name))?;
+    Ok(())
+}
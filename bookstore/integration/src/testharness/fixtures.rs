@@ -0,0 +1,64 @@
+use std::path::{Path, PathBuf};
+
+use bookstore::database::Database;
+use color_eyre::eyre::{Context, bail};
+use serde::Deserialize;
+
+use super::{TestHarness, factories::BookFactory};
+
+/// Directory of the fixture files
+pub const FIXTURES: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/integration/fixtures");
+
+/// Rows of a YAML fixture, by table. Every row is built by the factory of its table, so it only
+/// needs the fields the tests care about.
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Fixture {
+    #[serde(default)]
+    book: Vec<BookFactory>,
+}
+
+/// Loads the fixture `name` into the database of the test. That's `<name>.yaml` in the fixtures
+/// directory, which works for every backend, or otherwise `<backend>/<name>.sql`, which is run as
+/// is.
+pub async fn load_fixture(harness: &TestHarness, name: &str) -> color_eyre::Result<()> {
+    let yaml = Path::new(FIXTURES).join(format!("{name}.yaml"));
+    if yaml.exists() {
+        let text = read(&yaml)?;
+        let fixture: Fixture = serde_yaml::from_str(&text)
+            .wrap_err_with(|| format!("Invalid fixture {}", yaml.display()))?;
+        for book in fixture.book {
+            book.insert(harness).await?;
+        }
+        return Ok(());
+    }
+
+    let sql = sql_fixture(&harness.database, name);
+    if !sql.exists() {
+        bail!(
+            "There is no fixture {name}, expected {} or {}",
+            yaml.display(),
+            sql.display()
+        );
+    }
+    let text = read(&sql)?;
+    match &harness.database {
+        Database::Postgres(pool) => sqlx::raw_sql(&text).execute(pool).await.map(drop),
+        Database::Sqlite(pool) => sqlx::raw_sql(&text).execute(pool).await.map(drop),
+    }
+    .wrap_err_with(|| format!("Could not load the fixture {}", sql.display()))
+}
+
+fn sql_fixture(database: &Database, name: &str) -> PathBuf {
+    let backend = match database {
+        Database::Postgres(_) => "postgres",
+        Database::Sqlite(_) => "sqlite",
+    };
+    Path::new(FIXTURES)
+        .join(backend)
+        .join(format!("{name}.sql"))
+}
+
+fn read(path: &Path) -> color_eyre::Result<String> {
+    std::fs::read_to_string(path).wrap_err_with(|| format!("Could not read {}", path.display()))
+}
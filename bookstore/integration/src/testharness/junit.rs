@@ -0,0 +1,93 @@
+use std::{fmt::Write, path::Path, time::Duration};
+
+use color_eyre::eyre::Context;
+
+use super::reporting::{Outcome, TestResult, strip_colors};
+
+/// Writes the results as a JUnit XML report, which CI systems read to show and track tests. The
+/// module of a test becomes its class, the way JUnit groups tests.
+pub fn write(
+    path: &Path,
+    suite: &str,
+    results: &[(&str, TestResult)],
+    elapsed: Duration,
+) -> color_eyre::Result<()> {
+    let count = |outcome| {
+        results
+            .iter()
+            .filter(|(_, result)| result.outcome == outcome)
+            .count()
+    };
+    let totals = format!(
+        r#"name="{}" tests="{}" failures="{}" errors="0" skipped="{}" time="{:.3}""#,
+        escape(suite),
+        results.len(),
+        count(Outcome::Failed),
+        count(Outcome::Ignored),
+        elapsed.as_secs_f64()
+    );
+
+    let mut xml = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+    writeln!(xml, "<testsuites {totals}>")?;
+    writeln!(xml, "  <testsuite {totals}>")?;
+    for (name, result) in results {
+        let (class, function) = name.rsplit_once("::").unwrap_or((suite, name));
+        write!(
+            xml,
+            r#"    <testcase classname="{}" name="{}" time="{:.3}""#,
+            escape(class),
+            escape(function),
+            result.duration.as_secs_f64()
+        )?;
+        xml.push_str(">\n");
+        match (&result.outcome, &result.failure) {
+            (Outcome::Ignored, _) => match &result.reason {
+                Some(reason) => writeln!(xml, "      <skipped message=\"{}\"/>", escape(reason))?,
+                None => xml.push_str("      <skipped/>\n"),
+            },
+            (_, Some(failure)) => writeln!(
+                xml,
+                "      <failure message=\"{}\">{}</failure>",
+                escape(&failure.message),
+                escape(&failure.details)
+            )?,
+            (_, None) => {}
+        }
+        if !result.logs.is_empty() {
+            writeln!(
+                xml,
+                "      <system-out>{}</system-out>",
+                escape(&strip_colors(&result.logs))
+            )?;
+        }
+        xml.push_str("    </testcase>\n");
+    }
+    xml.push_str("  </testsuite>\n</testsuites>\n");
+
+    if let Some(directory) = path
+        .parent()
+        .filter(|parent| !parent.as_os_str().is_empty())
+    {
+        std::fs::create_dir_all(directory)
+            .wrap_err_with(|| format!("Could not create {}", directory.display()))?;
+    }
+    std::fs::write(path, xml)
+        .wrap_err_with(|| format!("Could not write the JUnit report to {}", path.display()))
+}
+
+fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&apos;"),
+            // Control characters, other than whitespace, are not allowed in XML at all
+            c if c.is_control() && !matches!(c, '\n' | '\r' | '\t') => {}
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
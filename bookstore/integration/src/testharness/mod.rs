@@ -1,28 +1,104 @@
+pub mod capture;
+pub mod client;
 pub mod dbtools;
+pub mod factories;
+pub mod fixtures;
+pub mod junit;
+pub mod options;
 pub mod reporting;
+pub mod server;
 pub mod testselector;
-use std::panic::AssertUnwindSafe;
+use std::{
+    any::Any,
+    fmt,
+    panic::AssertUnwindSafe,
+    sync::Arc,
+    time::{Duration, Instant},
+};
 
+use bookstore::{appstate::AppState, create_router, database::Database};
+use capture::{CapturedLogs, TEST_SPAN};
+use client::TestClient;
 use color_eyre::eyre::bail;
-use dbtools::create_administrative_database;
+use dbtools::DatabaseProvider;
 use futures::{
     FutureExt, SinkExt, StreamExt,
     channel::mpsc::{Receiver, Sender},
     future::{BoxFuture, join_all},
 };
-use reporting::{setup_logging, setup_reporting, specific_envfilter};
-use sqlx::{Pool, Postgres};
-use testselector::{TestSelector, create_test_selector};
-use tracing::{Instrument, debug, error, info, info_span};
+use options::HarnessOptions;
+use reporting::{
+    Failure, Report, TestResult, print_list, setup_logging, setup_reporting, specific_envfilter,
+};
+use server::{ServerMode, TestServer};
+use testselector::select_tests;
+use tokio::sync::Semaphore;
+use tracing::{Instrument, Span, debug, error, error_span, info};
 use tracing_subscriber::EnvFilter;
 
+/// A test of the harness, registered through `#[integration_test]`
 pub struct IntegrationTestCase {
     pub name: &'static str,
     pub fun: fn(TestHarness) -> TestReturn,
+    /// Only runs with `--ignored` or `--include-ignored`
+    pub ignore: bool,
+    /// How long the test may run before it fails, instead of `--test-timeout`
+    pub timeout: Option<Duration>,
+    /// The test runs with `--tag` for any of these
+    pub tags: &'static [&'static str],
+    /// The test passes by returning an error, or panicking
+    pub should_fail: bool,
+    /// Loaded into the database of the test before it runs, see [`fixtures::load_fixture`]
+    pub fixtures: &'static [&'static str],
+}
+
+/// Name of the test at `path`, which is the path of its function without the crate
+pub const fn test_name(path: &'static str) -> &'static str {
+    let bytes = path.as_bytes();
+    let mut i = 0;
+    while i + 1 < bytes.len() {
+        if bytes[i] == b':' && bytes[i + 1] == b':' {
+            let (_, name) = bytes.split_at(i + 2);
+            return match std::str::from_utf8(name) {
+                Ok(name) => name,
+                Err(_) => path,
+            };
+        }
+        i += 1;
+    }
+    path
 }
 
 pub type TestReturn = BoxFuture<'static, color_eyre::Result<()>>;
 
+/// Returned by a test that can't run where it is running, like on a backend lacking what it
+/// tests. It is reported as ignored, with the reason, instead of passing without testing anything.
+///
+///     let Database::Postgres(pool) = &harness.database else {
+///         return skip("Only postgres can notify");
+///     };
+pub fn skip(reason: impl Into<String>) -> color_eyre::Result<()> {
+    Err(Skipped(reason.into()).into())
+}
+
+#[derive(Debug)]
+struct Skipped(String);
+
+impl fmt::Display for Skipped {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Skipped: {}", self.0)
+    }
+}
+
+impl std::error::Error for Skipped {}
+
+/// Tests running at the same time, unless `--test-threads` says otherwise. Every test has a pool of
+/// up to 4 connections, which keeps them well within the 100 connections postgres allows by default.
+const DEFAULT_PARALLELISM: usize = 8;
+
+/// How long a test may run, unless `--test-timeout`, or the test itself says otherwise
+const DEFAULT_TEST_TIMEOUT: Duration = Duration::from_secs(60);
+
 pub fn run_tests(
     calling_module_name: &'static str,
     default_directives: &str,
@@ -32,103 +108,341 @@ pub fn run_tests(
         format!("<{calling_module_name}"),
     ]);
 
-    let tests: Box<dyn Iterator<Item = &'static IntegrationTestCase>> =
-        match create_test_selector()? {
-            TestSelector::Specific(test) => {
-                setup_logging(specific_envfilter(default_directives));
-                Box::new(std::iter::once(test)) as _
-            }
-            TestSelector::All(tests) => {
-                setup_logging(EnvFilter::from_default_env());
-                tests
-            }
-        };
+    let options = HarnessOptions::from_args()?;
+    let selection = select_tests(&options);
+    if options.list {
+        print_list(options.format, &selection);
+        return Ok(());
+    }
+    // Debugging a single test is the common case for wanting its logs
+    if selection.run.len() == 1 {
+        setup_logging(specific_envfilter(default_directives), !options.nocapture);
+    } else {
+        setup_logging(EnvFilter::from_default_env(), !options.nocapture);
+    }
     let runtime = tokio::runtime::Runtime::new().expect("Can't create a tokio runtime");
-    let r = runtime.block_on(async {
-        let admin_conn = create_administrative_database().await;
+
+    let started = Instant::now();
+    let mut report = Report::start(
+        calling_module_name,
+        options.format,
+        options.show_output,
+        options.junit.clone(),
+        selection.run.len(),
+    );
+    for case in &selection.ignored {
+        report.record(case.name, TestResult::ignored());
+    }
+    let tests = selection.run.iter().copied();
+    let results = runtime.block_on(async {
+        let databases = DatabaseProvider::from_env().await?;
+        if options.purge_stale {
+            let purged = databases.purge_stale().await?;
+            eprintln!("Removed {purged} stale test database(s)");
+        }
+        let permits = Arc::new(Semaphore::new(
+            options.test_threads.unwrap_or(DEFAULT_PARALLELISM),
+        ));
+        let run_deadline = options
+            .run_timeout
+            .map(|timeout| tokio::time::Instant::now() + timeout);
         let mut handles = vec![];
         for case in tests {
-            let span = info_span!("test_case", name = &case.name);
-            let _guard = span.clone().entered();
-            let connection = admin_conn.create_application_pool(case.name).await.unwrap();
-            let (mut harness, mut recv) = make_testharness(connection);
-            tokio::spawn(
-                async {
-                    recv.listen().await;
-                    recv.clean();
-                }
-                .instrument(span.clone()),
-            );
-            info!("Running test");
-
-            let span_clone = span.clone();
-            let test_handle = tokio::spawn(
-                async move {
-                    let result = AssertUnwindSafe((case.fun)(harness.clone()))
-                        .catch_unwind()
-                        .instrument(span_clone.clone())
-                        .await;
-
-                    match result {
-                        // test success
-                        Ok(Ok(_)) => {
-                            debug!("Test success");
-                            (case.name, true)
-                        }
-                        // test failed
-                        Ok(Err(e)) => {
-                            error!("Test {} has failed: {e:?}", &case.name);
-                            harness.mark_failed().await;
-                            (case.name, false)
-                        }
-                        // Thread paniced (most often from a failed assert_eq!() call)
-                        // The error here has nothing useful, because it's just a Box<dyn Any>, so
-                        // no reason to print it. The error is going to be visible anyway, because
-                        // rust prints the panic reason
-                        Err(_e) => {
-                            harness.mark_failed().await;
-                            (case.name, false)
-                        }
-                    }
-                }
-                .instrument(span.clone()),
-            );
-            handles.push(test_handle);
-        }
-
-        join_all(handles)
-            .await
-            .into_iter()
-            .all(|handle| match handle {
-                Ok((name, is_success)) => {
-                    let f = if is_success { "ok" } else { "fail" };
-                    println!("test {} ... {}", name, f);
-                    is_success
-                }
-                Err(e) => {
-                    // The tasks are running with .catch_unwind(), which means that we should never
-                    // encounter a failure when joining on them
-                    error!("A test task handle panicked, this should not be possible: {e:?}");
-                    false
-                }
-            })
-    });
-    if r {
+            let limits = Limits {
+                permits: permits.clone(),
+                timeout: case
+                    .timeout
+                    .or(options.test_timeout)
+                    .unwrap_or(DEFAULT_TEST_TIMEOUT),
+                run_deadline,
+                keep_all: options.keep_all,
+            };
+            // At the level of errors, so the errors of a test, logged by default, name the test, and
+            // are captured with its logs
+            let span = error_span!(TEST_SPAN, name = &case.name);
+            handles.push(tokio::spawn(
+                run_case(case, databases.clone(), options.server.clone(), limits).instrument(span),
+            ));
+        }
+        Ok::<_, color_eyre::Report>(join_all(handles).await)
+    })?;
+    for (handle, case) in results.into_iter().zip(&selection.run) {
+        let result = handle.unwrap_or_else(|e| {
+            // The tasks are running with .catch_unwind(), which means that we should never
+            // encounter a failure when joining on them
+            error!("A test task handle panicked, this should not be possible: {e:?}");
+            TestResult::failed(
+                Duration::ZERO,
+                Failure::new(format!("The test task panicked: {e}")),
+            )
+        });
+        report.record(case.name, result);
+    }
+    if report.finish(selection.filtered_out, started.elapsed())? {
         Ok(())
     } else {
         bail!("Some test(s) have failed")
     }
 }
 
+/// What a single test may use, and for how long
+struct Limits {
+    /// One is held by every running test, to bound how many run at the same time
+    permits: Arc<Semaphore>,
+    timeout: Duration,
+    /// Every test still running by then fails, and the ones that haven't started never do
+    run_deadline: Option<tokio::time::Instant>,
+    keep_all: bool,
+}
+
+/// Runs a test on a database of its own once there is a permit for it. Creating the database and
+/// setting the test up count towards its timeout, like running it does.
+async fn run_case(
+    case: &'static IntegrationTestCase,
+    databases: DatabaseProvider,
+    server: ServerMode,
+    limits: Limits,
+) -> TestResult {
+    let Some(permit) = before(limits.run_deadline, limits.permits.clone().acquire_owned()).await
+    else {
+        let message = "Could not start before the run timed out";
+        error!("Test {}: {message}", case.name);
+        return TestResult::failed(Duration::ZERO, Failure::new(message));
+    };
+    let _permit = permit.expect("The semaphore is never closed");
+    let started = Instant::now();
+    let test_deadline = tokio::time::Instant::now() + limits.timeout;
+    let deadline = match limits.run_deadline {
+        Some(run_deadline) if run_deadline < test_deadline => run_deadline,
+        _ => test_deadline,
+    };
+
+    let created = before(Some(deadline), databases.create_test_database(case.name)).await;
+    let database = match created {
+        Some(Ok(database)) => database,
+        Some(Err(e)) => {
+            error!("Could not create the database of test {}: {e:?}", case.name);
+            let failure = Failure::new(format!("Could not create the database: {e:#}"));
+            return with_captured_logs(TestResult::failed(started.elapsed(), failure));
+        }
+        None => {
+            let failure = timed_out(case, deadline, &limits, "creating its database");
+            return with_captured_logs(TestResult::failed(started.elapsed(), failure));
+        }
+    };
+    let (mut harness, mut recv) = make_testharness(database.clone());
+    let listener = tokio::spawn(
+        async move {
+            recv.listen().await;
+            recv
+        }
+        .in_current_span(),
+    );
+    info!("Running test");
+
+    let set_up = set_up(case, &mut harness, &databases, &server);
+    let verdict = match before(Some(deadline), set_up).await {
+        Some(Ok(server)) => {
+            let verdict = run_test(case, harness.clone(), deadline, &limits).await;
+            if let Some(server) = server {
+                server.stop().await;
+            }
+            verdict
+        }
+        Some(Err(e)) => {
+            error!("Could not set up test {}: {e:?}", case.name);
+            Verdict::Failed(Failure::new(format!("Could not set up the test: {e:#}")))
+        }
+        None => Verdict::Failed(timed_out(case, deadline, &limits, "setting up")),
+    };
+    let duration = started.elapsed();
+    if matches!(verdict, Verdict::Failed(_)) {
+        harness.mark_failed().await;
+    }
+    // The receiver stops listening once every harness is gone
+    drop(harness);
+    let recv = listener
+        .await
+        .expect("Listening to a test harness never panics");
+    recv.clean(case.name, &databases, database, limits.keep_all)
+        .await;
+    with_captured_logs(match verdict {
+        Verdict::Passed => TestResult::passed(duration),
+        Verdict::Skipped(reason) => TestResult::skipped(reason),
+        Verdict::Failed(failure) => TestResult::failed(duration, failure),
+    })
+}
+
+/// `result` with the logs captured in the span of the test
+fn with_captured_logs(result: TestResult) -> TestResult {
+    match CapturedLogs::of(&Span::current()) {
+        Some(logs) => result.with_logs(logs.take()),
+        None => result,
+    }
+}
+
+/// Why the test failed, when `deadline` passed while it was `doing` something. The error is logged
+/// in the span of the test, which tells where it was waiting.
+fn timed_out(
+    case: &'static IntegrationTestCase,
+    deadline: tokio::time::Instant,
+    limits: &Limits,
+    doing: &str,
+) -> Failure {
+    if Some(deadline) == limits.run_deadline {
+        error!(
+            "Test {} was still {doing} when the run timed out",
+            case.name
+        );
+        Failure::new(format!("Still {doing} when the run timed out"))
+    } else {
+        error!(
+            "Test {} has timed out after {:?}, while {doing}",
+            case.name, limits.timeout
+        );
+        Failure::new(format!(
+            "Timed out after {:?}, while {doing}",
+            limits.timeout
+        ))
+    }
+}
+
+/// Loads the fixtures of the test, and starts the server its requests go to, if they go to one
+async fn set_up(
+    case: &'static IntegrationTestCase,
+    harness: &mut TestHarness,
+    databases: &DatabaseProvider,
+    server: &ServerMode,
+) -> color_eyre::Result<Option<TestServer>> {
+    for fixture in case.fixtures {
+        harness.load_fixture(fixture).await?;
+    }
+    let server = match server {
+        ServerMode::Router => return Ok(None),
+        ServerMode::External(url) => {
+            harness.server_url = Some(url.trim_end_matches('/').to_string());
+            return Ok(None);
+        }
+        ServerMode::Serve => TestServer::serve(create_router(harness.app_state())).await?,
+        ServerMode::Binary => TestServer::binary(&databases.url(&harness.database)).await?,
+    };
+    harness.server_url = Some(server.url().to_string());
+    Ok(Some(server))
+}
+
+/// What came of running a test
+enum Verdict {
+    Passed,
+    /// The test called [`skip`]
+    Skipped(String),
+    Failed(Failure),
+}
+
+/// Runs the test until `deadline`, telling why it failed if it did
+async fn run_test(
+    case: &'static IntegrationTestCase,
+    harness: TestHarness,
+    deadline: tokio::time::Instant,
+    limits: &Limits,
+) -> Verdict {
+    let result = before(
+        Some(deadline),
+        AssertUnwindSafe((case.fun)(harness)).catch_unwind(),
+    )
+    .await;
+    let failure = match result {
+        Some(Ok(Err(e))) if e.is::<Skipped>() => {
+            info!("{e}");
+            return match e.downcast::<Skipped>() {
+                Ok(Skipped(reason)) => Verdict::Skipped(reason),
+                Err(e) => Verdict::Failed(Failure::from_report(&e)),
+            };
+        }
+        Some(Ok(Ok(_))) if case.should_fail => {
+            error!("Test {} was expected to fail, but it passed", &case.name);
+            Some(Failure::new("Expected to fail, but it passed"))
+        }
+        // test success
+        Some(Ok(Ok(_))) => {
+            debug!("Test success");
+            None
+        }
+        Some(Ok(Err(e))) if case.should_fail => {
+            debug!("Test failed as expected: {e:?}");
+            None
+        }
+        // test failed
+        Some(Ok(Err(e))) => {
+            error!("Test {} has failed: {e:?}", &case.name);
+            Some(Failure::from_report(&e))
+        }
+        Some(Err(_)) if case.should_fail => None,
+        // Thread paniced (most often from a failed assert_eq!() call)
+        // There is no need to log the panic, rust prints the panic reason anyway
+        Some(Err(panic)) => Some(Failure::new(panic_message(panic.as_ref()))),
+        // The test is dropped where it was waiting
+        None => Some(timed_out(case, deadline, limits, "running")),
+    };
+    match failure {
+        Some(failure) => Verdict::Failed(failure),
+        None => Verdict::Passed,
+    }
+}
+
+/// The message a test panicked with, which is a string, unless something else was given to
+/// `std::panic::panic_any`
+fn panic_message(panic: &(dyn Any + Send)) -> String {
+    if let Some(message) = panic.downcast_ref::<&str>() {
+        message.to_string()
+    } else if let Some(message) = panic.downcast_ref::<String>() {
+        message.clone()
+    } else {
+        String::from("The test panicked")
+    }
+}
+
+/// Awaits `future` until `deadline`, if there is one. None if the deadline passed first.
+async fn before<F: Future>(deadline: Option<tokio::time::Instant>, future: F) -> Option<F::Output> {
+    match deadline {
+        Some(deadline) => tokio::time::timeout_at(deadline, future).await.ok(),
+        None => Some(future.await),
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct TestHarness {
     tx: Sender<TestMessage>,
-    pub connection: Pool<Postgres>,
+    pub database: Database,
+    /// The server the requests of the test go to, instead of the router in process
+    server_url: Option<String>,
 }
 
 impl TestHarness {
-    pub fn new(tx: Sender<TestMessage>, connection: Pool<Postgres>) -> Self {
-        Self { tx, connection }
+    pub fn new(tx: Sender<TestMessage>, database: Database) -> Self {
+        Self {
+            tx,
+            database,
+            server_url: None,
+        }
+    }
+
+    /// Application state backed by the database of this test
+    pub fn app_state(&self) -> AppState {
+        self.database.app_state()
+    }
+
+    /// Client sending requests to the application, backed by the database of this test. They go
+    /// to the server of the test instead, when the tests run against servers.
+    pub fn app(&self) -> TestClient {
+        match &self.server_url {
+            Some(url) => TestClient::http(url),
+            None => TestClient::new(create_router(self.app_state())),
+        }
+    }
+
+    /// Loads the fixture `name` into the database of this test, see [`fixtures::load_fixture`]
+    pub async fn load_fixture(&self, name: &str) -> color_eyre::Result<()> {
+        fixtures::load_fixture(self, name).await
     }
 
     pub async fn mark_failed(&mut self) {
@@ -136,10 +450,10 @@ impl TestHarness {
     }
 }
 
-pub fn make_testharness(connection: Pool<Postgres>) -> (TestHarness, TestHarnessReceiver) {
+pub fn make_testharness(database: Database) -> (TestHarness, TestHarnessReceiver) {
     let (tx, rx) = futures::channel::mpsc::channel(100);
     (
-        TestHarness { tx, connection },
+        TestHarness::new(tx, database),
         TestHarnessReceiver::new_from_rx(rx),
     )
 }
@@ -171,20 +485,24 @@ impl TestHarnessReceiver {
         }
     }
 
-    pub fn clean(self) {
+    /// Drops the database of the test, unless the test failed, or every database is kept. The
+    /// location of a kept database is printed, so it can be inspected.
+    pub async fn clean(
+        self,
+        test_name: &str,
+        databases: &DatabaseProvider,
+        database: Database,
+        keep_all: bool,
+    ) {
+        if self.should_clean && !keep_all {
+            databases.drop_test_database(database).await;
+            return;
+        }
+        let location = databases.location(&database);
         if self.should_clean {
-            // for folder_to_clean_up in self.temp_folders {
-            //     debug!(
-            //         "Removing temporary directory {}",
-            //         folder_to_clean_up.display()
-            //     );
-            //     if let Err(e) = std::fs::remove_dir_all(&folder_to_clean_up) {
-            //         warn!(
-            //             "Could not clean up temporary folder '{}', it is lingering now: {e}",
-            //             folder_to_clean_up.display()
-            //         );
-            //     }
-            // }
+            info!(location, "Keeping the database");
+        } else {
+            eprintln!("The database of the failed test {test_name} is kept at {location}");
         }
     }
 }
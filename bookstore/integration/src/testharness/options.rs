@@ -0,0 +1,216 @@
+use std::{path::PathBuf, time::Duration};
+
+use color_eyre::eyre::{Context, bail, eyre};
+
+use super::server::ServerMode;
+
+const USAGE: &str = "\
+Usage: cargo test --test integration -- [OPTIONS] [FILTERS...]
+
+Runs the tests whose name contains any of the filters, every test without filters.
+
+Options:
+    --exact             Filters and skips match whole test names only
+    --skip FILTER       Skip the tests whose name contains FILTER, can be given more than once
+    --tag TAG           Run the tests tagged with TAG only, can be given more than once
+    --list              List the tests instead of running them
+    --ignored           Run the ignored tests only
+    --include-ignored   Run the ignored tests too
+    --test-threads N    Number of tests running at the same time, 8 by default
+    --test-timeout SECS Fail the tests running longer than this, 60 by default
+    --run-timeout SECS  Fail the tests still running after this, counted from the start of the run
+    --format FORMAT     pretty, terse, or json for a libtest style stream of events
+    --junit PATH        Write a JUnit XML report to PATH, TEST_JUNIT_REPORT works too
+    -q, --quiet         Same as --format terse
+    --nocapture         Print the logs of the tests right away, instead of with their outcome
+    --show-output       Print the logs of the tests that passed too
+    --server MODE       router to send requests straight to the router, serve for axum::serve, or
+                        binary for the bookstore binary, started for every test, TEST_SERVER works too
+    --base-url URL      Run the tests tagged smoke against the server at URL, TEST_BASE_URL works too
+    --keep-all          Keep the databases of the tests that passed too
+    --purge-stale       Remove the test databases left behind by earlier runs first
+
+The other libtest options IDEs and tools pass, such as --report-time, --shuffle, --shuffle-seed N,
+--logfile PATH, --color WHEN or -Z FLAG, are accepted and have no effect.
+";
+
+/// How the outcome of every test is printed
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum OutputFormat {
+    /// A `test <name> ... ok` line per test
+    #[default]
+    Pretty,
+    /// A single character per test
+    Terse,
+    /// A JSON object per line for every event, the way libtest prints them
+    Json,
+}
+
+/// Command line of the integration test binary. Follows the flags of the libtest harness, so
+/// cargo, and the IDEs calling it, can drive this one too.
+#[derive(Debug, Default)]
+pub struct HarnessOptions {
+    /// Only the tests matching any of these run, every test if empty
+    pub filters: Vec<String>,
+    /// The tests matching any of these don't run, even if they match a filter
+    pub skip: Vec<String>,
+    /// Only the tests with any of these tags run, every test if empty
+    pub tags: Vec<String>,
+    /// Filters and skips match whole test names, instead of any part of them
+    pub exact: bool,
+    /// List the tests instead of running them
+    pub list: bool,
+    /// Run the ignored tests only
+    pub ignored: bool,
+    /// Run the ignored tests, along with the others
+    pub include_ignored: bool,
+    /// Tests running at the same time
+    pub test_threads: Option<usize>,
+    /// How long a test may run, unless it sets a timeout of its own
+    pub test_timeout: Option<Duration>,
+    /// How long the whole run may take
+    pub run_timeout: Option<Duration>,
+    pub format: OutputFormat,
+    /// Print logs right away, instead of collecting them per test
+    pub nocapture: bool,
+    /// Print the logs of the tests that passed too, not only of the ones that failed
+    pub show_output: bool,
+    /// Where to write a JUnit report of the run
+    pub junit: Option<PathBuf>,
+    /// Where the requests of the tests go
+    pub server: ServerMode,
+    /// Keep the databases of the tests that passed too, not only of the ones that failed
+    pub keep_all: bool,
+    /// Remove the test databases left behind by earlier runs before running the tests
+    pub purge_stale: bool,
+}
+
+impl HarnessOptions {
+    /// Parses the arguments of the process, printing the usage and exiting on `--help`
+    pub fn from_args() -> color_eyre::Result<Self> {
+        let args: Vec<String> = std::env::args().skip(1).collect();
+        if args.iter().any(|arg| arg == "-h" || arg == "--help") {
+            print!("{USAGE}");
+            std::process::exit(0);
+        }
+        let mut options = Self::parse(args)?;
+        if options.test_threads.is_none() {
+            options.test_threads = std::env::var("RUST_TEST_THREADS")
+                .ok()
+                .map(|threads| parse_threads(&threads))
+                .transpose()?;
+        }
+        if options.junit.is_none() {
+            options.junit = std::env::var_os("TEST_JUNIT_REPORT").map(PathBuf::from);
+        }
+        if options.server == ServerMode::Router {
+            if let Ok(url) = std::env::var("TEST_BASE_URL") {
+                options.server = ServerMode::External(url);
+            } else if let Ok(mode) = std::env::var("TEST_SERVER") {
+                options.server = parse_server(&mode)?;
+            }
+        }
+        Ok(options)
+    }
+
+    pub fn parse(args: impl IntoIterator<Item = String>) -> color_eyre::Result<Self> {
+        let mut options = HarnessOptions::default();
+        let mut server = None;
+        let mut base_url = None;
+        let mut args = args.into_iter();
+        while let Some(arg) = args.next() {
+            // Both `--flag value` and `--flag=value` are accepted
+            let (flag, inline_value) = match arg.split_once('=') {
+                Some((flag, value)) if flag.starts_with("--") => (flag, Some(value.to_string())),
+                _ => (arg.as_str(), None),
+            };
+            let mut value = || {
+                inline_value
+                    .clone()
+                    .or_else(|| args.next())
+                    .ok_or_else(|| eyre!("Option {flag} needs a value"))
+            };
+            match flag {
+                "--exact" => options.exact = true,
+                "--skip" => options.skip.push(value()?),
+                "--tag" => options.tags.push(value()?),
+                "--list" => options.list = true,
+                "--ignored" => options.ignored = true,
+                "--include-ignored" => options.include_ignored = true,
+                "--test-threads" => options.test_threads = Some(parse_threads(&value()?)?),
+                "--test-timeout" => options.test_timeout = Some(parse_seconds(&value()?)?),
+                "--run-timeout" => options.run_timeout = Some(parse_seconds(&value()?)?),
+                "--format" => {
+                    options.format = match value()?.as_str() {
+                        "pretty" => OutputFormat::Pretty,
+                        "terse" => OutputFormat::Terse,
+                        "json" => OutputFormat::Json,
+                        other => {
+                            bail!("Unsupported format {other}, expected pretty, terse or json")
+                        }
+                    }
+                }
+                "-q" | "--quiet" => options.format = OutputFormat::Terse,
+                "--nocapture" | "--no-capture" => options.nocapture = true,
+                "--show-output" => options.show_output = true,
+                "--junit" => options.junit = Some(PathBuf::from(value()?)),
+                "--server" => server = Some(parse_server(&value()?)?),
+                "--base-url" => base_url = Some(value()?),
+                // libtest wants `-Z unstable-options` along with `--format json`, this one doesn't.
+                // The rest are libtest options IDE runners pass, with nothing to do here.
+                "--color" | "-Z" | "--shuffle-seed" | "--logfile" => {
+                    value()?;
+                }
+                "--report-time"
+                | "--ensure-time"
+                | "--shuffle"
+                | "--exclude-should-panic"
+                | "--force-run-in-process"
+                | "--test"
+                | "--bench" => {}
+                // The same, joined, like `-Zunstable-options`
+                flag if flag.starts_with("-Z") => {}
+                "--keep-all" => options.keep_all = true,
+                "--purge-stale" => options.purge_stale = true,
+                flag if flag.starts_with('-') => {
+                    bail!("Unknown option {flag}, see --help for the supported ones")
+                }
+                filter => options.filters.push(filter.to_string()),
+            }
+        }
+        if options.ignored && options.include_ignored {
+            bail!("--ignored and --include-ignored can't be used together");
+        }
+        options.server = match (server, base_url) {
+            (Some(_), Some(_)) => bail!("--server and --base-url can't be used together"),
+            (Some(server), None) => server,
+            (None, Some(url)) => ServerMode::External(url),
+            (None, None) => ServerMode::Router,
+        };
+        Ok(options)
+    }
+}
+
+fn parse_threads(threads: &str) -> color_eyre::Result<usize> {
+    match threads.parse() {
+        Ok(0) => bail!("The number of test threads can't be 0"),
+        result => result.wrap_err_with(|| format!("Invalid number of test threads '{threads}'")),
+    }
+}
+
+fn parse_server(mode: &str) -> color_eyre::Result<ServerMode> {
+    match mode {
+        "router" => Ok(ServerMode::Router),
+        "serve" => Ok(ServerMode::Serve),
+        "binary" => Ok(ServerMode::Binary),
+        other => bail!("Unsupported server {other}, expected router, serve or binary"),
+    }
+}
+
+fn parse_seconds(seconds: &str) -> color_eyre::Result<Duration> {
+    match seconds.parse() {
+        Ok(0) => bail!("A timeout can't be 0 seconds"),
+        Ok(seconds) => Ok(Duration::from_secs(seconds)),
+        Err(e) => Err(e).wrap_err_with(|| format!("Invalid number of seconds '{seconds}'")),
+    }
+}
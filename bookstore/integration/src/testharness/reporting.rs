@@ -1,5 +1,16 @@
+use std::{io::Write, path::PathBuf, time::Duration};
+
+use serde_json::json;
+
 use tracing_subscriber::{EnvFilter, fmt, layer::SubscriberExt, util::SubscriberInitExt};
 
+use super::{
+    capture::{CaptureLayer, CaptureWriter},
+    junit,
+    options::OutputFormat,
+    testselector::TestSelection,
+};
+
 pub fn setup_reporting(frames_to_keep: Vec<String>) {
     color_eyre::config::HookBuilder::default()
         .add_frame_filter(Box::new(move |frames| {
@@ -20,11 +31,27 @@ pub fn setup_reporting(frames_to_keep: Vec<String>) {
         .expect("Could not install eyre hook");
 }
 
-pub fn setup_logging(envfilter: EnvFilter) {
-    tracing_subscriber::registry()
-        .with(fmt::layer().without_time())
-        .with(envfilter)
-        .init();
+/// Logs through `envfilter`. With `capture`, the logs of every test are collected instead of
+/// printed right away, to be printed together with its outcome. They are collected without colors,
+/// since they end up in reports too.
+pub fn setup_logging(envfilter: EnvFilter, capture: bool) {
+    let registry = tracing_subscriber::registry().with(envfilter);
+    if capture {
+        registry
+            .with(CaptureLayer)
+            .with(
+                fmt::layer()
+                    .without_time()
+                    .with_ansi(false)
+                    .with_writer(CaptureWriter),
+            )
+            .init();
+    } else {
+        // stdout only has the report, which tools read with --format json
+        registry
+            .with(fmt::layer().without_time().with_writer(std::io::stderr))
+            .init();
+    }
 }
 
 pub fn specific_envfilter(default_directives: &str) -> EnvFilter {
@@ -41,3 +68,301 @@ pub fn specific_envfilter(default_directives: &str) -> EnvFilter {
         EnvFilter::try_new(default_directives).unwrap()
     }
 }
+
+/// What came of a single test
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Outcome {
+    Passed,
+    Failed,
+    Ignored,
+}
+
+/// Why a test failed, without colors
+#[derive(Clone, Debug)]
+pub struct Failure {
+    /// A single line
+    pub message: String,
+    /// Everything there is to know, like the backtrace
+    pub details: String,
+}
+
+impl Failure {
+    /// A failure with nothing more to it than `details`, the first line of which is its message
+    pub fn new(details: impl Into<String>) -> Self {
+        let details = details.into();
+        Self {
+            message: details.lines().next().unwrap_or_default().to_string(),
+            details,
+        }
+    }
+
+    pub fn from_report(report: &color_eyre::Report) -> Self {
+        Self {
+            message: strip_colors(&format!("{report:#}")),
+            details: strip_colors(&format!("{report:?}")),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct TestResult {
+    pub outcome: Outcome,
+    pub duration: Duration,
+    pub failure: Option<Failure>,
+    /// Why the test was skipped while it ran, see [`skip`](super::skip)
+    pub reason: Option<String>,
+    /// What the test logged, unless logs are printed right away
+    pub logs: String,
+}
+
+impl TestResult {
+    pub fn passed(duration: Duration) -> Self {
+        Self {
+            outcome: Outcome::Passed,
+            duration,
+            failure: None,
+            reason: None,
+            logs: String::new(),
+        }
+    }
+
+    pub fn failed(duration: Duration, failure: Failure) -> Self {
+        Self {
+            outcome: Outcome::Failed,
+            duration,
+            failure: Some(failure),
+            reason: None,
+            logs: String::new(),
+        }
+    }
+
+    pub fn ignored() -> Self {
+        Self {
+            outcome: Outcome::Ignored,
+            duration: Duration::ZERO,
+            failure: None,
+            reason: None,
+            logs: String::new(),
+        }
+    }
+
+    /// Ignored by the test itself, for `reason`
+    pub fn skipped(reason: String) -> Self {
+        Self {
+            reason: Some(reason),
+            ..Self::ignored()
+        }
+    }
+
+    pub fn with_logs(self, logs: String) -> Self {
+        Self { logs, ..self }
+    }
+}
+
+/// Prints the outcome of the tests the same way libtest does, so tools reading its output can
+/// read ours too, and writes them as a JUnit report if asked to
+pub struct Report {
+    suite: &'static str,
+    format: OutputFormat,
+    /// Print the logs of the tests that passed too, not only of the ones that failed
+    show_output: bool,
+    junit: Option<PathBuf>,
+    results: Vec<(&'static str, TestResult)>,
+}
+
+impl Report {
+    pub fn start(
+        suite: &'static str,
+        format: OutputFormat,
+        show_output: bool,
+        junit: Option<PathBuf>,
+        tests: usize,
+    ) -> Self {
+        match format {
+            OutputFormat::Json => print_event(json!({
+                "type": "suite",
+                "event": "started",
+                "test_count": tests,
+            })),
+            _ => println!("\nrunning {tests} {}", plural(tests, "test")),
+        }
+        Self {
+            suite,
+            format,
+            show_output,
+            junit,
+            results: vec![],
+        }
+    }
+
+    pub fn record(&mut self, name: &'static str, result: TestResult) {
+        match (self.format, result.outcome) {
+            (OutputFormat::Pretty, Outcome::Passed) => println!("test {name} ... ok"),
+            (OutputFormat::Pretty, Outcome::Failed) => println!("test {name} ... FAILED"),
+            (OutputFormat::Pretty, Outcome::Ignored) => match &result.reason {
+                Some(reason) => println!("test {name} ... ignored, {reason}"),
+                None => println!("test {name} ... ignored"),
+            },
+            (OutputFormat::Terse, Outcome::Passed) => print!("."),
+            (OutputFormat::Terse, Outcome::Failed) => print!("F"),
+            (OutputFormat::Terse, Outcome::Ignored) => print!("i"),
+            (OutputFormat::Json, outcome) => {
+                print_event(json!({ "type": "test", "event": "started", "name": name }));
+                let mut event = json!({
+                    "type": "test",
+                    "name": name,
+                    "event": match outcome {
+                        Outcome::Passed => "ok",
+                        Outcome::Failed => "failed",
+                        Outcome::Ignored => "ignored",
+                    },
+                });
+                if outcome != Outcome::Ignored {
+                    event["exec_time"] = json!(result.duration.as_secs_f64());
+                }
+                if let Some(reason) = &result.reason {
+                    event["message"] = json!(reason);
+                }
+                let stdout = match &result.failure {
+                    Some(failure) => format!("{}\n{}", failure.details, result.logs),
+                    None if self.show_output => result.logs.clone(),
+                    None => String::new(),
+                };
+                let stdout = strip_colors(&stdout);
+                if !stdout.trim().is_empty() {
+                    event["stdout"] = json!(stdout);
+                }
+                print_event(event);
+            }
+        }
+        let _ = std::io::stdout().flush();
+        self.results.push((name, result));
+    }
+
+    /// Prints the summary, and writes the JUnit report, returning whether every test that ran
+    /// passed
+    pub fn finish(self, filtered_out: usize, elapsed: Duration) -> color_eyre::Result<bool> {
+        let count = |outcome| {
+            self.results
+                .iter()
+                .filter(|(_, result)| result.outcome == outcome)
+                .count()
+        };
+        let (passed, failed, ignored) = (
+            count(Outcome::Passed),
+            count(Outcome::Failed),
+            count(Outcome::Ignored),
+        );
+        let result = if failed == 0 { "ok" } else { "FAILED" };
+
+        if self.format == OutputFormat::Json {
+            print_event(json!({
+                "type": "suite",
+                "event": if failed == 0 { "ok" } else { "failed" },
+                "passed": passed,
+                "failed": failed,
+                "ignored": ignored,
+                "measured": 0,
+                "filtered_out": filtered_out,
+                "exec_time": elapsed.as_secs_f64(),
+            }));
+        } else {
+            if self.format == OutputFormat::Terse && !self.results.is_empty() {
+                println!();
+            }
+            if self.show_output {
+                self.print_section("successes", Outcome::Passed);
+            }
+            self.print_section("failures", Outcome::Failed);
+            println!(
+                "\ntest result: {result}. {passed} passed; {failed} failed; {ignored} ignored; 0 measured; {filtered_out} filtered out; finished in {:.2}s\n",
+                elapsed.as_secs_f64()
+            );
+        }
+
+        if let Some(path) = &self.junit {
+            junit::write(path, self.suite, &self.results, elapsed)?;
+        }
+        Ok(failed == 0)
+    }
+}
+
+impl Report {
+    /// Prints why the tests with `outcome` failed and their logs, grouped by test, followed by
+    /// their names. The reason is printed whatever the logs it was also logged to let through.
+    fn print_section(&self, title: &str, outcome: Outcome) {
+        let results: Vec<_> = self
+            .results
+            .iter()
+            .filter(|(_, result)| result.outcome == outcome)
+            .collect();
+        if results.is_empty() {
+            return;
+        }
+        println!("\n{title}:");
+        for (name, result) in &results {
+            if let Some(failure) = &result.failure {
+                println!("\n---- {name} ----\n{}", failure.details.trim_end());
+            }
+            if !result.logs.is_empty() {
+                println!("\n---- {name} logs ----\n{}", result.logs.trim_end());
+            }
+        }
+        println!("\n{title}:");
+        for (name, _) in &results {
+            println!("    {name}");
+        }
+    }
+}
+
+fn print_event(event: serde_json::Value) {
+    println!("{event}");
+}
+
+/// Removes the ANSI escape sequences color_eyre colors its reports with
+pub fn strip_colors(text: &str) -> String {
+    let mut stripped = String::with_capacity(text.len());
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        if c == '\u{1b}' {
+            // A sequence is ESC [, parameters, and a letter ending it
+            for c in chars.by_ref() {
+                if c.is_ascii_alphabetic() {
+                    break;
+                }
+            }
+        } else {
+            stripped.push(c);
+        }
+    }
+    stripped
+}
+
+/// Lists the selected tests the way `--list` of libtest does
+pub fn print_list(format: OutputFormat, selection: &TestSelection) {
+    let mut names: Vec<&str> = selection
+        .run
+        .iter()
+        .chain(&selection.ignored)
+        .map(|case| case.name)
+        .collect();
+    names.sort_unstable();
+    for name in &names {
+        println!("{name}: test");
+    }
+    if format == OutputFormat::Pretty {
+        println!(
+            "\n{} {}, 0 benchmarks",
+            names.len(),
+            plural(names.len(), "test")
+        );
+    }
+}
+
+fn plural(count: usize, word: &str) -> String {
+    if count == 1 {
+        word.to_string()
+    } else {
+        format!("{word}s")
+    }
+}
@@ -0,0 +1,191 @@
+use std::{net::SocketAddr, process::Stdio, time::Duration};
+
+use axum::Router;
+use color_eyre::eyre::{Context, bail, eyre};
+use serde::Deserialize;
+use tokio::{
+    io::{AsyncBufReadExt, AsyncRead, BufReader},
+    net::TcpListener,
+    process::{Child, Command},
+    sync::oneshot,
+    task::JoinHandle,
+};
+use tracing::{Instrument, debug, info, warn};
+
+/// Target of the log line the binary tells the address it listens on with
+const LISTENING_TARGET: &str = "bookstore::listening";
+
+/// How long the bookstore binary may take to get ready
+const STARTUP_TIMEOUT: Duration = Duration::from_secs(20);
+
+/// Where the requests of `TestHarness::app` go
+#[derive(Clone, Debug, Default, PartialEq)]
+pub enum ServerMode {
+    /// Straight to the router, in process
+    #[default]
+    Router,
+    /// To the router, served by `axum::serve` on a port of its own for every test
+    Serve,
+    /// To the bookstore binary, started for every test, on the database of the test
+    Binary,
+    /// To a server running somewhere else, like a deployment, which only the smoke tests run
+    /// against
+    External(String),
+}
+
+/// A server running the application for a single test, stopped once the test is over
+pub struct TestServer {
+    url: String,
+    running: Running,
+}
+
+enum Running {
+    Task {
+        shutdown: oneshot::Sender<()>,
+        task: JoinHandle<std::io::Result<()>>,
+    },
+    Process(Child),
+}
+
+impl TestServer {
+    /// Serves `router` with `axum::serve`, the way the binary does, on a free port
+    pub async fn serve(router: Router) -> color_eyre::Result<Self> {
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let address = listener.local_addr()?;
+        let (shutdown, stopped) = oneshot::channel();
+        let server = axum::serve(
+            listener,
+            router.into_make_service_with_connect_info::<SocketAddr>(),
+        )
+        .with_graceful_shutdown(async {
+            stopped.await.ok();
+        });
+        let task = tokio::spawn(server.into_future().in_current_span());
+        debug!(%address, "Serving the application");
+        Ok(Self {
+            url: format!("http://{address}"),
+            running: Running::Task { shutdown, task },
+        })
+    }
+
+    /// Starts the bookstore binary on `database_url`, and waits until it's ready. What it prints
+    /// goes to the logs of the test.
+    pub async fn binary(database_url: &str) -> color_eyre::Result<Self> {
+        let mut child = Command::new(env!("CARGO_BIN_EXE_bookstore"))
+            .env("DATABASE_URL", database_url)
+            // The binary picks a free port itself, and tells which one in its logs
+            .env("BIND_TO", "127.0.0.1:0")
+            .env("LOG_FORMAT", "json")
+            .env("RUST_LOG", binary_log_filter())
+            .env_remove("DATABASE_REPLICA_URL")
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .kill_on_drop(true)
+            .spawn()
+            .wrap_err("Could not start the bookstore binary")?;
+        let (listening, address) = oneshot::channel();
+        if let Some(stdout) = child.stdout.take() {
+            tokio::spawn(forward_output(stdout, Some(listening)).in_current_span());
+        }
+        if let Some(stderr) = child.stderr.take() {
+            tokio::spawn(forward_output(stderr, None).in_current_span());
+        }
+        let address = match tokio::time::timeout(STARTUP_TIMEOUT, address).await {
+            Ok(Ok(address)) => address,
+            Ok(Err(_)) => bail!("The bookstore binary exited before it started listening"),
+            Err(_) => bail!("The bookstore binary was not listening after {STARTUP_TIMEOUT:?}"),
+        };
+        debug!(%address, pid = child.id(), "Started the bookstore binary");
+
+        let server = Self {
+            url: format!("http://{address}"),
+            running: Running::Process(child),
+        };
+        server.wait_until_ready().await
+    }
+
+    pub fn url(&self) -> &str {
+        &self.url
+    }
+
+    async fn wait_until_ready(mut self) -> color_eyre::Result<Self> {
+        let client = reqwest::Client::new();
+        let ready = format!("{}/ready", self.url);
+        let deadline = tokio::time::Instant::now() + STARTUP_TIMEOUT;
+        loop {
+            let exited = match &mut self.running {
+                Running::Process(child) => child.try_wait()?,
+                Running::Task { .. } => None,
+            };
+            if let Some(status) = exited {
+                bail!("The bookstore binary exited with {status} before it got ready");
+            }
+            match client.get(&ready).send().await {
+                Ok(response) if response.status().is_success() => return Ok(self),
+                _ if tokio::time::Instant::now() > deadline => {
+                    return Err(eyre!(
+                        "The bookstore binary was not ready after {STARTUP_TIMEOUT:?}"
+                    ));
+                }
+                _ => tokio::time::sleep(Duration::from_millis(50)).await,
+            }
+        }
+    }
+
+    /// Stops the server. The router served in process finishes the requests still running first,
+    /// the binary is killed.
+    pub async fn stop(self) {
+        match self.running {
+            Running::Task { shutdown, task } => {
+                let _ = shutdown.send(());
+                match task.await {
+                    Ok(Ok(())) => {}
+                    Ok(Err(e)) => warn!("The server of the test failed: {e}"),
+                    Err(e) => warn!("The server of the test panicked: {e}"),
+                }
+            }
+            Running::Process(mut child) => {
+                if let Err(e) = child.kill().await {
+                    warn!("Could not stop the bookstore binary: {e}");
+                }
+            }
+        }
+    }
+}
+
+/// `RUST_LOG` of the harness, with the line telling the address always enabled, as the most
+/// specific directive for its target
+fn binary_log_filter() -> String {
+    let filter = std::env::var("RUST_LOG").unwrap_or_else(|_| String::from("info"));
+    format!("{filter},{LISTENING_TARGET}=info")
+}
+
+/// Logs every line of `output`, and sends the address the binary listens on to `listening` once
+/// it tells it
+async fn forward_output(
+    output: impl AsyncRead + Unpin,
+    mut listening: Option<oneshot::Sender<SocketAddr>>,
+) {
+    let mut lines = BufReader::new(output).lines();
+    while let Ok(Some(line)) = lines.next_line().await {
+        match (listening_address(&line), listening.take()) {
+            (Some(address), Some(sender)) => {
+                let _ = sender.send(address);
+            }
+            (_, sender) => listening = sender,
+        }
+        info!(target: "bookstore", "{line}");
+    }
+}
+
+/// The address in the JSON log line of the binary telling where it listens
+fn listening_address(line: &str) -> Option<SocketAddr> {
+    #[derive(Deserialize)]
+    struct Listening {
+        message: String,
+        address: SocketAddr,
+    }
+    let event: Listening = serde_json::from_str(line).ok()?;
+    (event.message == "Listening").then_some(event.address)
+}
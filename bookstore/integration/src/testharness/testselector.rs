@@ -1,21 +1,64 @@
-use color_eyre::eyre::bail;
+use super::{IntegrationTestCase, options::HarnessOptions, server::ServerMode};
 
-use super::IntegrationTestCase;
+/// The tests that can run against a deployed server, which only send requests to it
+pub const SMOKE_TAG: &str = "smoke";
 
-pub enum TestSelector {
-    Specific(&'static IntegrationTestCase),
-    All(Box<dyn Iterator<Item = &'static IntegrationTestCase>>),
+/// The tests that don't send their requests through `TestHarness::app`, like those building a
+/// router with a state of their own, so a server started for the test would go unused
+pub const IN_PROCESS_TAG: &str = "in_process";
+
+/// The tests picked by the command line, in the order of their names
+pub struct TestSelection {
+    /// The tests to run
+    pub run: Vec<&'static IntegrationTestCase>,
+    /// The tests matching the filters that are not run, because they are ignored
+    pub ignored: Vec<&'static IntegrationTestCase>,
+    /// How many tests the filters, `--ignored`, or the server mode left out
+    pub filtered_out: usize,
 }
 
-pub fn create_test_selector() -> color_eyre::Result<TestSelector> {
-    let mut iter = inventory::iter::<IntegrationTestCase>.into_iter();
-    if let Some(filter_name) = std::env::args().nth(1) {
-        let test = iter.find(|&case| case.name.starts_with(&filter_name));
-        if let Some(test) = test {
-            return Ok(TestSelector::Specific(test));
+pub fn select_tests(options: &HarnessOptions) -> TestSelection {
+    let matches = |name: &str, pattern: &String| {
+        if options.exact {
+            name == pattern
+        } else {
+            name.contains(pattern.as_str())
+        }
+    };
+    let mut all: Vec<&'static IntegrationTestCase> =
+        inventory::iter::<IntegrationTestCase>.into_iter().collect();
+    all.sort_by_key(|case| case.name);
+
+    let mut selection = TestSelection {
+        run: vec![],
+        ignored: vec![],
+        filtered_out: 0,
+    };
+    for case in all {
+        let wanted = (options.filters.is_empty()
+            || options
+                .filters
+                .iter()
+                .any(|filter| matches(case.name, filter)))
+            && !options.skip.iter().any(|skip| matches(case.name, skip))
+            && (options.tags.is_empty()
+                || options
+                    .tags
+                    .iter()
+                    .any(|tag| case.tags.contains(&tag.as_str())))
+            && (case.ignore || !options.ignored)
+            && match options.server {
+                ServerMode::Router => true,
+                ServerMode::Serve | ServerMode::Binary => !case.tags.contains(&IN_PROCESS_TAG),
+                ServerMode::External(_) => case.tags.contains(&SMOKE_TAG),
+            };
+        if !wanted {
+            selection.filtered_out += 1;
+        } else if case.ignore && !options.ignored && !options.include_ignored {
+            selection.ignored.push(case);
+        } else {
+            selection.run.push(case);
         }
-        bail!("No tests found beginning with name {filter_name}");
-    } else {
-        Ok(TestSelector::All(Box::new(iter)))
     }
+    selection
 }
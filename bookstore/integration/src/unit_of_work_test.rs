@@ -0,0 +1,107 @@
+use std::sync::{
+    Arc,
+    atomic::{AtomicUsize, Ordering},
+};
+
+use bookstore::{
+    appstate::AppState,
+    handlers::BookRegistration,
+    unit_of_work::{IsolationLevel, TransactionOptions},
+};
+use color_eyre::eyre::{OptionExt, bail};
+use integration_macros::integration_test;
+
+use crate::testharness::TestHarness;
+
+fn registration(name: &str) -> BookRegistration {
+    BookRegistration {
+        name: name.to_string(),
+        description: String::from("A book registered in a transaction"),
+    }
+}
+
+#[integration_test(tags("in_process"))]
+async fn unit_of_work_commit(harness: TestHarness) -> color_eyre::Result<()> {
+    let state = harness.app_state();
+    let book = state
+        .transaction(TransactionOptions::default(), |uow| {
+            Box::pin(async move {
+                let book = uow
+                    .books
+                    .register_book(&registration("Piranesi"))
+                    .await?
+                    .ok_or_eyre("The book already exists")?;
+                // Reads inside the transaction see its own writes
+                assert!(uow.books.get_book_by_id(book.id).await?.is_some());
+                Ok(book)
+            })
+        })
+        .await?;
+
+    let stored = state.books.get_book_by_id(book.id).await?;
+    assert_eq!(stored.map(|b| b.name), Some(book.name));
+    Ok(())
+}
+
+#[integration_test(tags("in_process"))]
+async fn unit_of_work_rollback_on_error(harness: TestHarness) -> color_eyre::Result<()> {
+    let state = harness.app_state();
+    let result: color_eyre::Result<()> = state
+        .transaction(TransactionOptions::default(), |uow| {
+            Box::pin(async move {
+                uow.books.register_book(&registration("Piranesi")).await?;
+                bail!("Something went wrong after registering")
+            })
+        })
+        .await;
+
+    assert!(result.is_err());
+    assert!(state.books.list_books().await?.is_empty());
+    Ok(())
+}
+
+#[integration_test(tags("in_process"))]
+async fn unit_of_work_retry_on_serialization_failure(
+    harness: TestHarness,
+) -> color_eyre::Result<()> {
+    retry_on_serialization_failure(harness.app_state()).await
+}
+
+#[integration_test(tags("in_process"))]
+async fn in_memory_unit_of_work_retry_on_serialization_failure(
+    _harness: TestHarness,
+) -> color_eyre::Result<()> {
+    retry_on_serialization_failure(AppState::in_memory()).await
+}
+
+async fn retry_on_serialization_failure(state: AppState) -> color_eyre::Result<()> {
+    let attempts = Arc::new(AtomicUsize::new(0));
+    let options = TransactionOptions {
+        isolation: IsolationLevel::RepeatableRead,
+        ..TransactionOptions::default()
+    };
+    let registered = state
+        .transaction(options, |uow| {
+            let state = state.clone();
+            let attempts = Arc::clone(&attempts);
+            Box::pin(async move {
+                let books = uow.books.list_books().await?;
+                if attempts.fetch_add(1, Ordering::SeqCst) == 0 {
+                    // Someone else registers the same book after the transaction took its
+                    // snapshot, so the transaction can't be serialized after it
+                    state.books.register_book(&registration("Piranesi")).await?;
+                }
+                let registered = uow.books.register_book(&registration("Piranesi")).await?;
+                Ok((books.len(), registered))
+            })
+        })
+        .await?;
+
+    // The retry sees the other registration, and backs off
+    assert_eq!(attempts.load(Ordering::SeqCst), 2);
+    let (seen, registered) = registered;
+    assert_eq!(seen, 1);
+    assert!(registered.is_none());
+    assert_eq!(state.books.list_books().await?.len(), 1);
+    Ok(())
+}
@@ -0,0 +1,2 @@
+DROP TRIGGER book_changed ON book;
+DROP FUNCTION notify_book_changed();
@@ -0,0 +1,15 @@
+-- Lets every instance invalidate its cache when any of them changes a book
+CREATE FUNCTION notify_book_changed() RETURNS trigger AS $$
+BEGIN
+    IF TG_OP = 'DELETE' THEN
+        PERFORM pg_notify('book_changed', OLD.id::text);
+    ELSE
+        PERFORM pg_notify('book_changed', NEW.id::text);
+    END IF;
+    RETURN NULL;
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER book_changed
+    AFTER INSERT OR UPDATE OR DELETE ON book
+    FOR EACH ROW EXECUTE FUNCTION notify_book_changed();
@@ -0,0 +1,3 @@
+DROP TRIGGER book_updated_at ON book;
+DROP FUNCTION touch_updated_at();
+ALTER TABLE book DROP COLUMN updated_at;
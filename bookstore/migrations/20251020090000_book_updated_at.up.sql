@@ -0,0 +1,12 @@
+ALTER TABLE book ADD COLUMN updated_at TIMESTAMPTZ NOT NULL DEFAULT now();
+
+CREATE FUNCTION touch_updated_at() RETURNS trigger AS $$
+BEGIN
+    NEW.updated_at = now();
+    RETURN NEW;
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER book_updated_at
+    BEFORE UPDATE ON book
+    FOR EACH ROW EXECUTE FUNCTION touch_updated_at();
@@ -0,0 +1,8 @@
+-- Token buckets of the rate limiter, shared by every instance
+CREATE TABLE rate_limit_bucket (
+    key TEXT NOT NULL PRIMARY KEY,
+    tokens DOUBLE PRECISION NOT NULL,
+    -- Whether the last request taking from this bucket was let through
+    allowed BOOLEAN NOT NULL,
+    updated_at TIMESTAMPTZ NOT NULL
+);
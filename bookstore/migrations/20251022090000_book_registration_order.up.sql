@@ -0,0 +1,3 @@
+-- Books are listed in the order they were registered, the way SQLite lists them by rowid. The
+-- books already there are numbered in whatever order the table keeps them.
+ALTER TABLE book ADD COLUMN registration_order BIGINT GENERATED ALWAYS AS IDENTITY;
@@ -0,0 +1,5 @@
+CREATE TABLE book (
+    id BLOB NOT NULL PRIMARY KEY,
+    name TEXT NOT NULL UNIQUE,
+    description TEXT NOT NULL
+);
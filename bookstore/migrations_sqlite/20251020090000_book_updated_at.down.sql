@@ -0,0 +1,2 @@
+DROP TRIGGER book_updated_at;
+ALTER TABLE book DROP COLUMN updated_at;
@@ -0,0 +1,18 @@
+-- SQLite can't add a column with a non-constant default, so the table is rebuilt instead. The
+-- timestamps are RFC 3339 text, which is what sqlx reads dates from.
+CREATE TABLE book_new (
+    id BLOB NOT NULL PRIMARY KEY,
+    name TEXT NOT NULL UNIQUE,
+    description TEXT NOT NULL,
+    updated_at TEXT NOT NULL DEFAULT (strftime('%Y-%m-%dT%H:%M:%fZ', 'now'))
+);
+INSERT INTO book_new (rowid, id, name, description) SELECT rowid, id, name, description FROM book;
+DROP TABLE book;
+ALTER TABLE book_new RENAME TO book;
+
+CREATE TRIGGER book_updated_at
+    AFTER UPDATE OF id, name, description ON book
+    FOR EACH ROW
+BEGIN
+    UPDATE book SET updated_at = strftime('%Y-%m-%dT%H:%M:%fZ', 'now') WHERE rowid = NEW.rowid;
+END;
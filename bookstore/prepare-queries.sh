@@ -0,0 +1,55 @@
+#!/usr/bin/env bash
+# Regenerates the offline query metadata in .sqlx, for both database backends.
+#
+# The query macros can only check against one database at a time, so this does one online build
+# per backend, each writing the metadata of the queries it could check into .sqlx. Needs sqlx-cli
+# (cargo install sqlx-cli --no-default-features --features postgres,sqlite), and a postgres server
+# at TEST_DATABASE_URL, same as the integration tests.
+set -euo pipefail
+cd "$(dirname "$0")"
+
+: "${TEST_DATABASE_URL:?TEST_DATABASE_URL must point at a postgres server}"
+sqlite_dir=$(mktemp -d)
+trap 'rm -rf "$sqlite_dir"' EXIT
+postgres_url="$TEST_DATABASE_URL/bookstore_sqlx_prepare"
+sqlite_url="sqlite:$sqlite_dir/bookstore.db"
+
+sqlx database reset -y --source migrations --database-url "$postgres_url"
+sqlx database reset -y --source migrations_sqlite --database-url "$sqlite_url"
+
+# Checks the queries against the database at <url>, writing the metadata of the ones it could
+# check. The queries in the <files> of the other backend can't check against it, so their errors
+# are expected, any other error fails the script.
+check_against() {
+    local url=$1
+    shift
+    local log="$sqlite_dir/check.log"
+    touch src/repository/*.rs src/rate_limit/*.rs
+    if SQLX_OFFLINE=false SQLX_OFFLINE_DIR="$PWD/.sqlx" DATABASE_URL="$url" \
+        cargo check --quiet --lib --message-format short 2>"$log"; then
+        return
+    fi
+    local expected
+    expected=$(printf '%s|' "$@")
+    if grep -E '(^|: )error' "$log" \
+        | grep -Ev "^(${expected%|}):|^error: could not compile" >/dev/null; then
+        cat "$log" >&2
+        echo "Checking the queries against $url failed" >&2
+        return 1
+    fi
+}
+
+rm -rf .sqlx
+mkdir .sqlx
+# SQLite also understands the $1 style parameters of the postgres queries, so the postgres pass has
+# to come last, to overwrite whatever the SQLite pass wrote for those
+check_against "$sqlite_url" src/repository/postgres.rs src/rate_limit/postgres.rs
+check_against "$postgres_url" src/repository/sqlite.rs
+
+# By now every query has to have its metadata. A query that is valid on both backends ends up
+# described by postgres, and fails here, those have to be written so only their own backend accepts
+# them.
+touch src/repository/*.rs src/rate_limit/*.rs
+SQLX_OFFLINE=true cargo check --lib
+
+sqlx database drop -y --database-url "$postgres_url"
@@ -1,68 +1,194 @@
+use std::{sync::Arc, time::Duration};
+
+use chrono::{DateTime, Utc};
+use futures::future::BoxFuture;
 use serde::{Deserialize, Serialize};
-use sqlx::{Pool, Postgres, prelude::FromRow};
+use sqlx::{Pool, Postgres, Sqlite, prelude::FromRow};
+use tracing::{debug, warn};
+use utoipa::ToSchema;
 use uuid::Uuid;
 
-use crate::handlers::BookRegistration;
+use crate::{
+    cache::{BookCache, CacheOptions, Source},
+    database::Database,
+    rate_limit::{ClientResolver, RateLimiter},
+    repository::{
+        BookRepository, CachedBookRepository, InMemoryBookRepository, PostgresBookRepository,
+        PostgresTransactions, ReplicatedBookRepository, SqliteBookRepository, SqliteTransactions,
+    },
+    unit_of_work::{TransactionManager, TransactionOptions, UnitOfWork, is_serialization_failure},
+};
 
 #[derive(Clone, Debug)]
 pub struct AppState {
-    pub pool: Pool<Postgres>,
+    pub books: Arc<dyn BookRepository>,
+    pub transactions: Arc<dyn TransactionManager>,
+    /// The database behind the repositories, if there is one. Used to check the schema.
+    pub database: Option<Database>,
+    /// Replica of the database that reads go to, if there is one
+    pub replica: Option<Database>,
+    pub cache: Option<BookCache>,
+    pub rate_limiter: Option<RateLimiter>,
+    /// Tells the clients apart, for rate limiting and for reading their own writes
+    pub clients: ClientResolver,
+    /// What `books` is composed of, so the cache and the replica can be added in any order
+    layers: Layers,
+}
+
+#[derive(Clone, Debug)]
+struct Layers {
+    primary: Arc<dyn BookRepository>,
+    replica: Option<(Arc<dyn BookRepository>, Duration)>,
 }
 
 impl AppState {
     pub fn new(pool: Pool<Postgres>) -> Self {
-        Self { pool }
+        Self::from_parts(
+            PostgresBookRepository::new(pool.clone()),
+            PostgresTransactions::new(pool.clone()),
+            Some(Database::Postgres(pool)),
+        )
+    }
+
+    pub fn sqlite(pool: Pool<Sqlite>) -> Self {
+        Self::from_parts(
+            SqliteBookRepository::new(pool.clone()),
+            SqliteTransactions::new(pool.clone()),
+            Some(Database::Sqlite(pool)),
+        )
     }
 
-    pub async fn book_exists(&self, name: &str) -> Result<bool, sqlx::Error> {
-        let mut conn = self.pool.acquire().await?;
-        let book: Option<Book> =
-            sqlx::query_as("SELECT id, name, description FROM book WHERE name = $1")
-                .bind(name)
-                .fetch_optional(&mut *conn)
-                .await?;
+    pub fn in_memory() -> Self {
+        let books = InMemoryBookRepository::new();
+        let transactions = books.transactions();
+        Self::from_parts(books, transactions, None)
+    }
+
+    pub fn from_parts(
+        books: impl BookRepository + 'static,
+        transactions: impl TransactionManager + 'static,
+        database: Option<Database>,
+    ) -> Self {
+        let books: Arc<dyn BookRepository> = Arc::new(books);
+        Self {
+            books: Arc::clone(&books),
+            transactions: Arc::new(transactions),
+            database,
+            replica: None,
+            cache: None,
+            rate_limiter: None,
+            clients: ClientResolver::new(),
+            layers: Layers {
+                primary: books,
+                replica: None,
+            },
+        }
+    }
 
-        Ok(book.is_some())
+    pub fn with_clients(self, clients: ClientResolver) -> Self {
+        Self { clients, ..self }
     }
 
-    pub async fn register_book(&self, book: &BookRegistration) -> color_eyre::Result<Book> {
-        let mut conn = self.pool.acquire().await?;
-        let id = Uuid::new_v4();
-        sqlx::query("INSERT INTO book (id, name, description) VALUES ($1, $2, $3)")
-            .bind(id)
-            .bind(&book.name)
-            .bind(&book.description)
-            .execute(&mut *conn)
-            .await?;
-
-        // safety: we know the book is going to exist, we just inserted it
-        Ok(self.get_book_by_id(id).await?.unwrap())
+    pub fn with_rate_limiter(self, rate_limiter: RateLimiter) -> Self {
+        Self {
+            rate_limiter: Some(rate_limiter),
+            ..self
+        }
     }
 
-    pub async fn get_book_by_id(&self, id: Uuid) -> color_eyre::Result<Option<Book>> {
-        let mut conn = self.pool.acquire().await?;
-        let book: Option<Book> =
-            sqlx::query_as("SELECT id, name, description FROM book WHERE id = $1")
-                .bind(id)
-                .fetch_optional(&mut *conn)
-                .await?;
+    /// Caches the books read from the repositories, the ones of the replica too, which get
+    /// entries of their own
+    pub fn with_cache(self, options: CacheOptions) -> Self {
+        Self {
+            cache: Some(BookCache::new(options)),
+            ..self
+        }
+        .compose()
+    }
+
+    /// Sends the reads of the books to `replica`, see [`ReplicatedBookRepository`]. Transactions
+    /// keep running on the primary.
+    pub fn with_replica(mut self, replica: Database, stickiness: Duration) -> Self {
+        self.layers.replica = Some((replica.app_state().books, stickiness));
+        Self {
+            replica: Some(replica),
+            ..self
+        }
+        .compose()
+    }
 
-        Ok(book)
+    /// Builds `books` from its layers again, the replica on top of the cache
+    fn compose(self) -> Self {
+        let cached = |books: &Arc<dyn BookRepository>, source| -> Arc<dyn BookRepository> {
+            match &self.cache {
+                Some(cache) => Arc::new(CachedBookRepository::new(
+                    Arc::clone(books),
+                    cache.clone(),
+                    source,
+                )),
+                None => Arc::clone(books),
+            }
+        };
+        let mut books = cached(&self.layers.primary, Source::Primary);
+        if let Some((replica, stickiness)) = &self.layers.replica {
+            books = Arc::new(ReplicatedBookRepository::new(
+                books,
+                cached(replica, Source::Replica),
+                *stickiness,
+            ));
+        }
+        Self { books, ..self }
     }
 
-    pub async fn list_books(&self) -> color_eyre::Result<Vec<Book>> {
-        let mut conn = self.pool.acquire().await?;
-        let books: Vec<Book> = sqlx::query_as("SELECT id, name, description FROM book")
-            .fetch_all(&mut *conn)
-            .await?;
+    /// Runs `work` in a single transaction, committing it if `work` succeeds, and rolling it back
+    /// if it fails. When the transaction fails because of a concurrent one, `work` is run again in
+    /// a new transaction, up to `options.max_retries` times.
+    pub async fn transaction<T, F>(
+        &self,
+        options: TransactionOptions,
+        work: F,
+    ) -> color_eyre::Result<T>
+    where
+        F: Fn(UnitOfWork) -> BoxFuture<'static, color_eyre::Result<T>>,
+    {
+        let mut attempt = 0;
+        loop {
+            let (unit_of_work, pending) = self.transactions.begin(options.isolation).await?;
+            let result = match work(unit_of_work).await {
+                Ok(value) => pending.commit().await.map(|_| {
+                    // The transaction isn't going through the cached repositories, so there is no
+                    // telling what it changed
+                    if let Some(cache) = &self.cache {
+                        cache.invalidate_all();
+                    }
+                    value
+                }),
+                Err(e) => {
+                    if let Err(rollback_error) = pending.rollback().await {
+                        warn!("Could not roll back transaction: {rollback_error:?}");
+                    }
+                    Err(e)
+                }
+            };
 
-        Ok(books)
+            match result {
+                Err(e) if attempt < options.max_retries && is_serialization_failure(&e) => {
+                    attempt += 1;
+                    debug!(
+                        attempt,
+                        "Retrying transaction after a serialization failure"
+                    );
+                }
+                result => return result,
+            }
+        }
     }
 }
 
-#[derive(Debug, Deserialize, Serialize, FromRow)]
+#[derive(Clone, Debug, Deserialize, Serialize, FromRow, ToSchema)]
 pub struct Book {
     pub id: Uuid,
     pub name: String,
     pub description: String,
+    pub updated_at: DateTime<Utc>,
 }
@@ -0,0 +1,244 @@
+use std::{
+    num::NonZeroUsize,
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicU64, Ordering},
+    },
+    time::{Duration, Instant},
+};
+
+use lru::LruCache;
+use sqlx::{Pool, Postgres, postgres::PgListener};
+use tokio::task::JoinHandle;
+use tracing::{debug, warn};
+use uuid::Uuid;
+
+use crate::appstate::Book;
+
+/// Channel the `book` table notifies on when a row changes, with the id of the book as payload
+pub const BOOK_CHANGED_CHANNEL: &str = "book_changed";
+
+#[derive(Clone, Copy, Debug)]
+pub struct CacheOptions {
+    /// How many books are kept, per source
+    pub capacity: NonZeroUsize,
+    /// How long an entry is served before it is looked up again. Bounds how stale an entry can
+    /// get when an invalidation is missed, e.g. for books read from a replica that lags behind.
+    pub ttl: Duration,
+    /// How long after an invalidation the books read from the replica aren't cached. The change
+    /// gets announced by the primary, so the replica may not have it yet, and caching what it
+    /// answers would keep serving the old book for the whole `ttl`.
+    pub replica_lag: Duration,
+}
+
+impl Default for CacheOptions {
+    fn default() -> Self {
+        Self {
+            // safety: not zero
+            capacity: NonZeroUsize::new(1000).unwrap(),
+            ttl: Duration::from_secs(30),
+            replica_lag: Duration::from_secs(5),
+        }
+    }
+}
+
+/// Where the cached books were read from. Books of the primary and of the replica are kept apart,
+/// so a client reading from the primary after its write never gets the replica's older copy.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Source {
+    Primary,
+    Replica,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct CacheStats {
+    pub hits: u64,
+    pub misses: u64,
+}
+
+struct Entry<T> {
+    value: T,
+    expires: Instant,
+}
+
+struct Entries {
+    books: LruCache<(Source, Uuid), Entry<Book>>,
+    lists: LruCache<Source, Entry<Vec<Book>>>,
+    /// Bumped on every invalidation
+    generation: u64,
+    invalidated_at: Option<Instant>,
+}
+
+/// Books by id and the list of books, kept in memory. Cloning is cheap, the clones share the
+/// entries.
+#[derive(Clone)]
+pub struct BookCache {
+    entries: Arc<Mutex<Entries>>,
+    ttl: Duration,
+    replica_lag: Duration,
+    hits: Arc<AtomicU64>,
+    misses: Arc<AtomicU64>,
+}
+
+impl std::fmt::Debug for BookCache {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("BookCache")
+            .field("ttl", &self.ttl)
+            .field("replica_lag", &self.replica_lag)
+            .field("stats", &self.stats())
+            .finish()
+    }
+}
+
+impl BookCache {
+    pub fn new(options: CacheOptions) -> Self {
+        let entries = Entries {
+            books: LruCache::new(options.capacity),
+            // safety: not zero
+            lists: LruCache::new(NonZeroUsize::new(2).unwrap()),
+            generation: 0,
+            invalidated_at: None,
+        };
+        Self {
+            entries: Arc::new(Mutex::new(entries)),
+            ttl: options.ttl,
+            replica_lag: options.replica_lag,
+            hits: Arc::default(),
+            misses: Arc::default(),
+        }
+    }
+
+    pub fn book(&self, source: Source, id: Uuid) -> Option<Book> {
+        let mut entries = self.entries.lock().unwrap();
+        let book = fresh(&mut entries.books, &(source, id));
+        self.count(book.is_some());
+        book
+    }
+
+    /// Take it before looking up what gets cached, and pass it to `put_book` or `put_list`. If
+    /// anything got invalidated in the meantime, what was looked up could already be stale, so it
+    /// isn't cached. Neither is what the replica answers right after an invalidation, see
+    /// [`CacheOptions::replica_lag`].
+    pub fn generation(&self) -> u64 {
+        self.entries.lock().unwrap().generation
+    }
+
+    pub fn put_book(&self, source: Source, book: Book, generation: u64) {
+        let entry = self.entry(book);
+        let mut entries = self.entries.lock().unwrap();
+        if self.cacheable(&entries, source, generation) {
+            entries.books.put((source, entry.value.id), entry);
+        }
+    }
+
+    pub fn list(&self, source: Source) -> Option<Vec<Book>> {
+        let mut entries = self.entries.lock().unwrap();
+        let list = fresh(&mut entries.lists, &source);
+        self.count(list.is_some());
+        list
+    }
+
+    pub fn put_list(&self, source: Source, books: Vec<Book>, generation: u64) {
+        let entry = self.entry(books);
+        let mut entries = self.entries.lock().unwrap();
+        if self.cacheable(&entries, source, generation) {
+            entries.lists.put(source, entry);
+        }
+    }
+
+    /// Forgets the book with `id`, and every list, since they could contain it
+    pub fn invalidate_book(&self, id: Uuid) {
+        let mut entries = self.entries.lock().unwrap();
+        for source in [Source::Primary, Source::Replica] {
+            entries.books.pop(&(source, id));
+        }
+        entries.lists.clear();
+        entries.generation += 1;
+        entries.invalidated_at = Some(Instant::now());
+    }
+
+    pub fn invalidate_all(&self) {
+        let mut entries = self.entries.lock().unwrap();
+        entries.books.clear();
+        entries.lists.clear();
+        entries.generation += 1;
+        entries.invalidated_at = Some(Instant::now());
+    }
+
+    pub fn stats(&self) -> CacheStats {
+        CacheStats {
+            hits: self.hits.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
+        }
+    }
+
+    /// Invalidates the books other instances change, by listening to the notifications of the
+    /// `book` table. Notifications sent while the connection is down are lost, so everything is
+    /// invalidated when it comes back.
+    pub fn listen(&self, pool: Pool<Postgres>) -> JoinHandle<()> {
+        let cache = self.clone();
+        tokio::spawn(async move {
+            loop {
+                if let Err(e) = cache.listen_until_failure(&pool).await {
+                    warn!("Lost the book change notifications, reconnecting: {e:?}");
+                }
+                cache.invalidate_all();
+                tokio::time::sleep(Duration::from_secs(1)).await;
+            }
+        })
+    }
+
+    async fn listen_until_failure(&self, pool: &Pool<Postgres>) -> Result<(), sqlx::Error> {
+        let mut listener = PgListener::connect_with(pool).await?;
+        listener.listen(BOOK_CHANGED_CHANNEL).await?;
+        // Anything could have changed before the listener was set up
+        self.invalidate_all();
+        // try_recv() returns None when the connection was lost, and reconnects on the next call
+        while let Some(notification) = listener.try_recv().await? {
+            match notification.payload().parse() {
+                Ok(id) => {
+                    debug!(%id, "Book changed, invalidating");
+                    self.invalidate_book(id);
+                }
+                Err(_) => self.invalidate_all(),
+            }
+        }
+        Ok(())
+    }
+
+    fn cacheable(&self, entries: &Entries, source: Source, generation: u64) -> bool {
+        let replica_caught_up = || {
+            entries
+                .invalidated_at
+                .is_none_or(|invalidated| invalidated.elapsed() >= self.replica_lag)
+        };
+        entries.generation == generation && (source == Source::Primary || replica_caught_up())
+    }
+
+    fn entry<T>(&self, value: T) -> Entry<T> {
+        Entry {
+            value,
+            expires: Instant::now() + self.ttl,
+        }
+    }
+
+    fn count(&self, hit: bool) {
+        let counter = if hit { &self.hits } else { &self.misses };
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+fn fresh<K, T>(cache: &mut LruCache<K, Entry<T>>, key: &K) -> Option<T>
+where
+    K: std::hash::Hash + Eq,
+    T: Clone,
+{
+    match cache.get(key) {
+        Some(entry) if entry.expires > Instant::now() => Some(entry.value.clone()),
+        Some(_) => {
+            cache.pop(key);
+            None
+        }
+        None => None,
+    }
+}
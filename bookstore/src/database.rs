@@ -0,0 +1,97 @@
+use std::{str::FromStr, time::Duration};
+
+use color_eyre::eyre::{Context, bail};
+use sqlx::{
+    Pool, Postgres, Sqlite,
+    pool::PoolOptions,
+    sqlite::{SqliteConnectOptions, SqliteJournalMode},
+};
+
+use crate::{
+    appstate::AppState,
+    migrations::{self, MigrationStatus, POSTGRES_MIGRATOR, SQLITE_MIGRATOR},
+};
+
+/// Connection pool of one of the supported database backends
+#[derive(Clone, Debug)]
+pub enum Database {
+    Postgres(Pool<Postgres>),
+    Sqlite(Pool<Sqlite>),
+}
+
+impl Database {
+    /// Connects to the database behind `database_url`. The backend is picked by the scheme of the
+    /// url: `postgres://` or `sqlite:`.
+    pub async fn connect(database_url: &str) -> color_eyre::Result<Self> {
+        if database_url.starts_with("postgres:") || database_url.starts_with("postgresql:") {
+            let pool = PoolOptions::<Postgres>::new()
+                .max_connections(5)
+                .acquire_timeout(Duration::from_secs(3))
+                .connect(database_url)
+                .await
+                .wrap_err("Could not connect to database")?;
+            Ok(Database::Postgres(pool))
+        } else if database_url.starts_with("sqlite:") {
+            // In WAL mode readers don't block writers, and a transaction that falls behind a
+            // concurrent write fails with SQLITE_BUSY_SNAPSHOT, which the unit of work retries
+            let options = SqliteConnectOptions::from_str(database_url)?
+                .create_if_missing(true)
+                .journal_mode(SqliteJournalMode::Wal);
+            let pool = PoolOptions::<Sqlite>::new()
+                .max_connections(5)
+                .acquire_timeout(Duration::from_secs(3))
+                .connect_with(options)
+                .await
+                .wrap_err("Could not open database")?;
+            Ok(Database::Sqlite(pool))
+        } else {
+            bail!("Unsupported database url {database_url}, expected a postgres:// or sqlite: url")
+        }
+    }
+
+    pub fn app_state(&self) -> AppState {
+        match self {
+            Database::Postgres(pool) => AppState::new(pool.clone()),
+            Database::Sqlite(pool) => AppState::sqlite(pool.clone()),
+        }
+    }
+
+    /// Applies every pending migration
+    pub async fn run_migrations(&self) -> Result<(), sqlx::migrate::MigrateError> {
+        match self {
+            Database::Postgres(pool) => POSTGRES_MIGRATOR.run(pool).await,
+            Database::Sqlite(pool) => SQLITE_MIGRATOR.run(pool).await,
+        }
+    }
+
+    /// Status of every migration, without writing to the database
+    pub async fn migration_status(
+        &self,
+    ) -> Result<Vec<MigrationStatus>, sqlx::migrate::MigrateError> {
+        match self {
+            Database::Postgres(pool) => {
+                let table_exists =
+                    sqlx::query_scalar("SELECT to_regclass('_sqlx_migrations') IS NOT NULL")
+                        .fetch_one(pool)
+                        .await?;
+                migrations::migration_status(&POSTGRES_MIGRATOR, pool, table_exists).await
+            }
+            Database::Sqlite(pool) => {
+                let table_exists = sqlx::query_scalar(
+                    "SELECT EXISTS (SELECT 1 FROM sqlite_master WHERE type = 'table' AND name = '_sqlx_migrations')",
+                )
+                .fetch_one(pool)
+                .await?;
+                migrations::migration_status(&SQLITE_MIGRATOR, pool, table_exists).await
+            }
+        }
+    }
+
+    /// Reverts the last `steps` applied migrations, returning the versions that got reverted
+    pub async fn rollback(&self, steps: usize) -> Result<Vec<i64>, sqlx::migrate::MigrateError> {
+        match self {
+            Database::Postgres(pool) => migrations::rollback(&POSTGRES_MIGRATOR, pool, steps).await,
+            Database::Sqlite(pool) => migrations::rollback(&SQLITE_MIGRATOR, pool, steps).await,
+        }
+    }
+}
@@ -1,40 +1,104 @@
-use crate::{appstate::AppState, util::AxumHandlerError};
+use crate::{
+    appstate::{AppState, Book},
+    http_cache::{Conditions, policy},
+    migrations::schema_is_current,
+    util::{AxumHandlerError, ProblemDetails},
+    validation::{PathParams, Validate, ValidatedJson, Validator},
+};
 use axum::{
     Json,
-    extract::{Path, State},
-    http::StatusCode,
+    extract::State,
+    http::{StatusCode, header},
     response::{IntoResponse, Response},
 };
 use serde::{Deserialize, Serialize};
 use tracing::{info, warn};
+use utoipa::ToSchema;
 use uuid::Uuid;
 
-#[derive(Deserialize, Serialize)]
+// Missing fields default to empty strings, so they get reported by the validation rules together
+// with every other problem, instead of failing deserialization one at a time
+#[derive(Default, Deserialize, Serialize, ToSchema)]
+#[serde(default)]
 pub struct BookRegistration {
+    #[schema(min_length = 1, max_length = 200)]
     pub name: String,
+    #[schema(min_length = 1, max_length = 5000)]
     pub description: String,
 }
 
+impl Validate for BookRegistration {
+    fn validate(&mut self, v: &mut Validator) {
+        v.string("name", &mut self.name)
+            .trim()
+            .required()
+            .max_length(200)
+            .allowed_chars(|c| !c.is_control(), "printable characters");
+        v.string("description", &mut self.description)
+            .trim()
+            .required()
+            .max_length(5000);
+    }
+}
+
+#[utoipa::path(
+    post,
+    path = "/book",
+    request_body = BookRegistration,
+    responses(
+        (status = 200, description = "The book was registered", body = Book),
+        (status = 400, description = "The request body isn't well-formed JSON", body = ProblemDetails, content_type = "application/problem+json"),
+        (status = 409, description = "A book with the same name already exists", body = ProblemDetails, content_type = "application/problem+json"),
+        (status = 415, description = "The request body isn't sent as application/json", body = ProblemDetails, content_type = "application/problem+json"),
+        (status = 422, description = "The request body contains invalid fields", body = ProblemDetails, content_type = "application/problem+json"),
+    )
+)]
 pub async fn register_new_book(
     State(state): State<AppState>,
-    body: Json<BookRegistration>,
+    ValidatedJson(body): ValidatedJson<BookRegistration>,
 ) -> Result<Response, AxumHandlerError> {
-    if state.book_exists(&body.name).await? {
+    let Some(book) = state.books.register_book(&body).await? else {
         warn!(
             "Tried registering a book that already exists: {}",
             body.name
         );
-        return Ok((StatusCode::CONFLICT, "Book already exists").into_response());
-    }
-    let book = state.register_book(&body).await?;
+        return Err(AxumHandlerError::Conflict {
+            msg: "Book already exists".into(),
+        });
+    };
     Ok(Json(book).into_response())
 }
 
+#[utoipa::path(
+    get,
+    path = "/book/{book_id}",
+    params(
+        ("book_id" = Uuid, Path, description = "Id of the book"),
+        ("If-None-Match" = Option<String>, Header, description = "Answer with 304 Not Modified if the book still has one of these entity tags"),
+        ("If-Modified-Since" = Option<String>, Header, description = "Answer with 304 Not Modified if the book didn't change since this HTTP date, unless If-None-Match is given"),
+    ),
+    responses(
+        (status = 200, description = "The book with the given id", body = Book, headers(
+            ("ETag" = String, description = "Weak entity tag of the book"),
+            ("Last-Modified" = String, description = "When the book last changed"),
+            ("Cache-Control" = String),
+        )),
+        (status = 304, description = "The book didn't change since the client's copy", headers(
+            ("ETag" = String, description = "Weak entity tag of the book"),
+            ("Last-Modified" = String, description = "When the book last changed"),
+            ("Cache-Control" = String),
+        )),
+        (status = 400, description = "The book id isn't a UUID", body = ProblemDetails, content_type = "application/problem+json"),
+        (status = 404, description = "There is no book with the given id", body = ProblemDetails, content_type = "application/problem+json"),
+    )
+)]
 pub async fn show_book(
-    Path(id): Path<Uuid>,
+    PathParams(id): PathParams<Uuid>,
     State(state): State<AppState>,
+    conditions: Conditions,
 ) -> Result<Response, AxumHandlerError> {
     let book = state
+        .books
         .get_book_by_id(id)
         .await?
         .ok_or(AxumHandlerError::NotFound {
@@ -42,10 +106,166 @@ pub async fn show_book(
         })?;
 
     info!(id = %book.id, name = %book.name, "Showing book");
-    Ok(Json(book).into_response())
+    Ok(conditions.respond(Some(book.updated_at), policy::BOOK, book))
+}
+
+#[utoipa::path(
+    get,
+    path = "/book",
+    params(
+        ("If-None-Match" = Option<String>, Header, description = "Answer with 304 Not Modified if the list still has one of these entity tags"),
+    ),
+    responses(
+        (status = 200, description = "Every registered book", body = Vec<Book>, headers(
+            ("ETag" = String, description = "Weak entity tag of the list"),
+            ("Cache-Control" = String),
+        )),
+        (status = 304, description = "The list didn't change since the client's copy", headers(
+            ("ETag" = String, description = "Weak entity tag of the list"),
+            ("Cache-Control" = String),
+        )),
+    )
+)]
+pub async fn list_books(
+    State(state): State<AppState>,
+    conditions: Conditions,
+) -> Result<Response, AxumHandlerError> {
+    let books = state.books.list_books().await?;
+    // The newest updated_at of the books doesn't tell whether one was added: it can be in the same
+    // second as the newest one, or before it, since it is set when the transaction starts, not when
+    // it commits
+    Ok(conditions.respond(None, policy::BOOK_LIST, books))
 }
 
-pub async fn list_books(State(state): State<AppState>) -> Result<Response, AxumHandlerError> {
-    let books = state.list_books().await?;
-    Ok(Json(books).into_response())
+/// Readiness probe. Reports unavailable while the database schema is behind this build, which can
+/// happen when the server runs with `MIGRATION_MODE=verify`.
+pub async fn readiness(State(state): State<AppState>) -> Result<StatusCode, AxumHandlerError> {
+    if let Some(database) = &state.database {
+        let statuses = database
+            .migration_status()
+            .await
+            .map_err(color_eyre::Report::from)?;
+        if !schema_is_current(&statuses) {
+            return Err(AxumHandlerError::Unavailable {
+                msg: "The database schema is not up to date".into(),
+            });
+        }
+    }
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Counters in the Prometheus text format
+pub async fn metrics(State(state): State<AppState>) -> impl IntoResponse {
+    let mut body = String::new();
+    if let Some(cache) = &state.cache {
+        let stats = cache.stats();
+        body.push_str(&format!(
+            "# TYPE bookstore_cache_hits_total counter\n\
+            bookstore_cache_hits_total {}\n\
+            # TYPE bookstore_cache_misses_total counter\n\
+            bookstore_cache_misses_total {}\n",
+            stats.hits, stats.misses
+        ));
+    }
+    ([(header::CONTENT_TYPE, "text/plain; version=0.0.4")], body)
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{
+        Router,
+        body::Body,
+        http::{Request, StatusCode},
+    };
+    use tower::ServiceExt;
+    use uuid::Uuid;
+
+    use crate::{appstate::AppState, create_router};
+
+    /// Sends the request to `app`, and returns the status and JSON body of the response
+    async fn send(app: &Router, request: Request<Body>) -> (StatusCode, serde_json::Value) {
+        let response = app.clone().oneshot(request).await.unwrap();
+        let status = response.status();
+        let bytes = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        (status, serde_json::from_slice(&bytes).unwrap_or_default())
+    }
+
+    fn register(body: serde_json::Value) -> Request<Body> {
+        Request::post("/v1/book")
+            .header("content-type", "application/json")
+            .body(Body::from(body.to_string()))
+            .unwrap()
+    }
+
+    fn ship_of_theseus() -> serde_json::Value {
+        serde_json::json!({
+            "name": "Ship of Theseus",
+            "description": "Elaborate book about two people tracking down a mysterious author",
+        })
+    }
+
+    #[tokio::test]
+    async fn register_then_show() {
+        let app = create_router(AppState::in_memory());
+        let (status, registered) = send(&app, register(ship_of_theseus())).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(registered["name"], "Ship of Theseus");
+
+        let id = registered["id"].as_str().unwrap();
+        let request = Request::get(format!("/v1/book/{id}"))
+            .body(Body::empty())
+            .unwrap();
+        let (status, shown) = send(&app, request).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(shown, registered);
+    }
+
+    #[tokio::test]
+    async fn register_conflict() {
+        let app = create_router(AppState::in_memory());
+        send(&app, register(ship_of_theseus())).await;
+        let (status, problem) = send(&app, register(ship_of_theseus())).await;
+        assert_eq!(status, StatusCode::CONFLICT);
+        assert_eq!(problem["status"], 409);
+        assert_eq!(problem["instance"], "/v1/book");
+    }
+
+    #[tokio::test]
+    async fn register_invalid() {
+        let app = create_router(AppState::in_memory());
+        let (status, problem) = send(&app, register(serde_json::json!({ "name": "   " }))).await;
+        assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+        assert_eq!(
+            problem["errors"],
+            serde_json::json!([
+                { "field": "name", "message": "is required" },
+                { "field": "description", "message": "is required" },
+            ])
+        );
+    }
+
+    #[tokio::test]
+    async fn show_missing() {
+        let app = create_router(AppState::in_memory());
+        let id = Uuid::new_v4();
+        let request = Request::get(format!("/v1/book/{id}"))
+            .body(Body::empty())
+            .unwrap();
+        let (status, problem) = send(&app, request).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        assert_eq!(problem["detail"], format!("Cannot find book with id {id}"));
+    }
+
+    #[tokio::test]
+    async fn show_invalid_id() {
+        let app = create_router(AppState::in_memory());
+        let request = Request::get("/v1/book/not-a-uuid")
+            .body(Body::empty())
+            .unwrap();
+        let (status, problem) = send(&app, request).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(problem["status"], 400);
+    }
 }
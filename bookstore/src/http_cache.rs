@@ -0,0 +1,122 @@
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use axum::{
+    Json,
+    body::Bytes,
+    extract::FromRequestParts,
+    http::{HeaderValue, StatusCode, header, request::Parts},
+    response::{IntoResponse, Response},
+};
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use tower_http::set_header::SetResponseHeaderLayer;
+
+/// `Cache-Control` policies of the routes
+pub mod policy {
+    /// Books hardly ever change, and clients can revalidate them cheaply
+    pub const BOOK: &str = "public, max-age=60";
+    /// New books show up in the list, so it is only kept for a short while
+    pub const BOOK_LIST: &str = "public, max-age=5";
+    /// The spec and the docs only change with a deploy
+    pub const DOCS: &str = "public, max-age=3600";
+    /// Probes and metrics have to be fresh every time
+    pub const NO_STORE: &str = "no-store";
+}
+
+/// Sets `Cache-Control` on the responses of a route that don't set it themselves
+pub fn cache_control(policy: &'static str) -> SetResponseHeaderLayer<HeaderValue> {
+    SetResponseHeaderLayer::if_not_present(header::CACHE_CONTROL, HeaderValue::from_static(policy))
+}
+
+/// The conditional headers of a request, `If-None-Match` and `If-Modified-Since`. Dates that can't
+/// be parsed are ignored, as if the header was missing.
+#[derive(Clone, Debug, Default)]
+pub struct Conditions {
+    pub if_none_match: Option<String>,
+    pub if_modified_since: Option<SystemTime>,
+}
+
+impl<S: Send + Sync> FromRequestParts<S> for Conditions {
+    type Rejection = std::convert::Infallible;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        let header = |name| {
+            parts
+                .headers
+                .get(name)
+                .and_then(|value: &HeaderValue| value.to_str().ok())
+        };
+        Ok(Conditions {
+            if_none_match: header(header::IF_NONE_MATCH).map(String::from),
+            if_modified_since: header(header::IF_MODIFIED_SINCE)
+                .and_then(|value| httpdate::parse_http_date(value).ok()),
+        })
+    }
+}
+
+impl Conditions {
+    /// Responds with `body`, or with 304 Not Modified if the client's copy is still current. The
+    /// `ETag` is a digest of the body, so it changes with any change. `last_modified` is `None`
+    /// for resources whose modification time doesn't tell every change, those are only validated
+    /// by their `ETag`.
+    pub fn respond<T: Serialize>(
+        self,
+        last_modified: Option<DateTime<Utc>>,
+        policy: &'static str,
+        body: T,
+    ) -> Response {
+        let json = match serde_json::to_vec(&body) {
+            Ok(json) => json,
+            // Json turns the error into a response
+            Err(_) => return Json(body).into_response(),
+        };
+        let etag = etag(&json);
+        // HTTP dates have no fractions of a second
+        let last_modified = last_modified
+            .map(|modified| UNIX_EPOCH + Duration::from_secs(modified.timestamp().max(0) as u64));
+        // If-None-Match takes precedence, If-Modified-Since is only used without it
+        let not_modified = match (&self.if_none_match, last_modified, self.if_modified_since) {
+            (Some(if_none_match), _, _) => matches_etag(if_none_match, &etag),
+            (None, Some(modified), Some(since)) => modified <= since,
+            (None, _, _) => false,
+        };
+        let mut response = if not_modified {
+            StatusCode::NOT_MODIFIED.into_response()
+        } else {
+            (
+                [(header::CONTENT_TYPE, "application/json")],
+                Bytes::from(json),
+            )
+                .into_response()
+        };
+        let headers = response.headers_mut();
+        headers.insert(header::CACHE_CONTROL, HeaderValue::from_static(policy));
+        // safety: the tag is made of hex digits only
+        headers.insert(header::ETAG, etag.try_into().unwrap());
+        if let Some(modified) = last_modified {
+            // safety: formatted HTTP dates are always valid header values
+            let value = httpdate::fmt_http_date(modified).try_into().unwrap();
+            headers.insert(header::LAST_MODIFIED, value);
+        }
+        response
+    }
+}
+
+/// A weak tag, since the compression of the response changes its bytes
+fn etag(body: &[u8]) -> String {
+    let digest = Sha256::digest(body);
+    let hex: String = digest[..16]
+        .iter()
+        .map(|byte| format!("{byte:02x}"))
+        .collect();
+    format!("W/\"{hex}\"")
+}
+
+/// Whether `if_none_match`, a list of tags or `*`, has `etag`, comparing them weakly
+fn matches_etag(if_none_match: &str, etag: &str) -> bool {
+    let opaque = |tag: &str| tag.trim().trim_start_matches("W/").to_string();
+    if_none_match
+        .split(',')
+        .any(|tag| tag.trim() == "*" || opaque(tag) == opaque(etag))
+}
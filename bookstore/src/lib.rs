@@ -1,51 +1,94 @@
 pub mod appstate;
 pub mod bookstore;
+pub mod cache;
+pub mod database;
 pub mod handlers;
+pub mod http_cache;
+pub mod logging;
+pub mod migrations;
+pub mod openapi;
+pub mod rate_limit;
+pub mod repository;
+pub mod unit_of_work;
 pub mod util;
+pub mod validation;
+pub mod versioning;
 
 use appstate::AppState;
 use axum::{
-    Router,
-    extract::Request,
+    Json, Router,
+    extract::{Request, State},
     middleware::{self, Next},
     response::Response,
     routing,
 };
-use handlers::{list_books, register_new_book, show_book};
+use http_cache::{cache_control, policy};
 use rand::Rng;
-use sqlx::PgConnection;
-use tracing::{Instrument, info, info_span};
+use std::time::Instant;
+use tower_http::compression::CompressionLayer;
+use tracing::{Instrument, field, info, info_span};
+use util::{CLIENT, REQUEST_PATH};
+use utoipa_scalar::{Scalar, Servable};
 
 pub fn create_router(app_state: AppState) -> Router {
-    Router::new()
-        .route("/book", routing::get(list_books))
-        .route("/book/{book_id}", routing::get(show_book))
-        .route("/book", routing::post(register_new_book))
-        .layer(middleware::from_fn(tracing_mw))
+    let (router, api) = openapi::api_router().split_for_parts();
+    let docs = Router::new()
+        .merge(Scalar::with_url("/docs", api.clone()))
+        .route("/openapi.json", routing::get(move || async { Json(api) }))
+        .layer(cache_control(policy::DOCS));
+    let operations = Router::new()
+        .route("/ready", routing::get(handlers::readiness))
+        .route("/metrics", routing::get(handlers::metrics))
+        .layer(cache_control(policy::NO_STORE));
+    router
+        .merge(docs)
+        .merge(operations)
+        .layer(middleware::from_fn_with_state(
+            app_state.clone(),
+            rate_limit::rate_limit_mw,
+        ))
+        .layer(CompressionLayer::new())
+        .layer(middleware::from_fn_with_state(
+            app_state.clone(),
+            tracing_mw,
+        ))
         .with_state(app_state)
 }
 
-async fn tracing_mw(req: Request, next: Next) -> Response {
+async fn tracing_mw(State(state): State<AppState>, req: Request, next: Next) -> Response {
     let request_id: String = rand::rng()
         .sample_iter(rand::distr::Alphanumeric)
         .take(4)
         .map(char::from)
         .collect();
     let method = req.method();
-    let path = req.uri().path();
+    let path = req.uri().path().to_string();
+    let client = state.clients.client(&req);
 
-    let span = info_span!("request", request_id);
-    info!(parent: &span, %method, path,  "Incoming request");
-    let mut res = next.run(req).instrument(span.clone()).await;
+    // Who sent the request, which never gives an API key away
+    let principal = client.as_deref();
+    let span = info_span!(
+        "request",
+        request_id,
+        %method,
+        %path,
+        principal,
+        status = field::Empty,
+        latency_ms = field::Empty
+    );
+    info!(parent: &span, "Incoming request");
+    let started = Instant::now();
+    let mut res = REQUEST_PATH
+        .scope(path, CLIENT.scope(client, next.run(req)))
+        .instrument(span.clone())
+        .await;
     res.headers_mut()
         // safety: the request id is always a valid header value, because it only contains alphanumeric characters
         .insert("x-request-id", request_id.try_into().unwrap());
 
     let status = res.status();
-    info!(parent: &span, status = status.as_u16(), "Response sent");
+    span.record("status", status.as_u16());
+    span.record("latency_ms", started.elapsed().as_millis() as u64);
+    info!(parent: &span, "Response sent");
     res
 }
-
-pub async fn run_migrations(conn: &mut PgConnection) -> Result<(), sqlx::migrate::MigrateError> {
-    sqlx::migrate!("./migrations").run(conn).await
-}
@@ -0,0 +1,164 @@
+use std::str::FromStr;
+
+use color_eyre::eyre::bail;
+use tracing::Subscriber;
+use tracing_subscriber::{
+    EnvFilter, Layer,
+    fmt::{self, MakeWriter},
+    layer::SubscriberExt,
+    registry::LookupSpan,
+    util::SubscriberInitExt,
+};
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum LogFormat {
+    /// Human readable, one line per event
+    #[default]
+    Text,
+    /// One JSON object per line, including the fields of the spans the event happened in, so the
+    /// log pipeline can index on things like `request_id`
+    Json,
+}
+
+impl FromStr for LogFormat {
+    type Err = color_eyre::Report;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "text" => Ok(LogFormat::Text),
+            "json" => Ok(LogFormat::Json),
+            other => bail!("Unknown log format '{other}', expected 'text' or 'json'"),
+        }
+    }
+}
+
+pub fn setup_logging(format: LogFormat) {
+    let registry = tracing_subscriber::registry().with(EnvFilter::from_default_env());
+    match format {
+        LogFormat::Text => registry.with(fmt::layer()).init(),
+        LogFormat::Json => registry.with(json_layer(std::io::stdout)).init(),
+    }
+}
+
+fn json_layer<S, W>(writer: W) -> impl Layer<S>
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+    W: for<'w> MakeWriter<'w> + Send + Sync + 'static,
+{
+    fmt::layer().json().flatten_event(true).with_writer(writer)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        io,
+        net::SocketAddr,
+        sync::{Arc, Mutex},
+    };
+
+    use async_trait::async_trait;
+    use axum::{
+        body::Body,
+        extract::ConnectInfo,
+        http::{Request, StatusCode},
+    };
+    use color_eyre::eyre::{Context, eyre};
+    use tower::ServiceExt;
+    use uuid::Uuid;
+
+    use super::*;
+    use crate::{
+        appstate::{AppState, Book},
+        create_router,
+        handlers::BookRegistration,
+        rate_limit::ClientResolver,
+        repository::{BookRepository, InMemoryBookRepository},
+    };
+
+    /// Keeps everything written to it
+    #[derive(Clone, Default)]
+    struct Output(Arc<Mutex<Vec<u8>>>);
+
+    impl io::Write for Output {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl<'a> MakeWriter<'a> for Output {
+        type Writer = Self;
+
+        fn make_writer(&'a self) -> Self::Writer {
+            self.clone()
+        }
+    }
+
+    #[derive(Debug)]
+    struct Unreachable;
+
+    #[async_trait]
+    impl BookRepository for Unreachable {
+        async fn register_book(&self, _: &BookRegistration) -> color_eyre::Result<Option<Book>> {
+            unimplemented!()
+        }
+
+        async fn get_book_by_id(&self, _: Uuid) -> color_eyre::Result<Option<Book>> {
+            unimplemented!()
+        }
+
+        async fn list_books(&self) -> color_eyre::Result<Vec<Book>> {
+            Err(eyre!("Connection refused")).wrap_err("Could not list the books")
+        }
+    }
+
+    #[tokio::test]
+    async fn json_lines_carry_request_and_error_chain() {
+        let output = Output::default();
+        let _guard = tracing_subscriber::registry()
+            .with(json_layer(output.clone()))
+            .set_default();
+        let transactions = InMemoryBookRepository::new().transactions();
+        let clients = ClientResolver::new().with_api_keys([String::from("secret")]);
+        let app = create_router(
+            AppState::from_parts(Unreachable, transactions, None).with_clients(clients),
+        );
+
+        let peer: SocketAddr = "203.0.113.7:40000".parse().unwrap();
+        let request = Request::get("/v1/book")
+            .header("x-api-key", "secret")
+            .extension(ConnectInfo(peer))
+            .body(Body::empty())
+            .unwrap();
+        let response = app.oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::INTERNAL_SERVER_ERROR);
+
+        let output = String::from_utf8(output.0.lock().unwrap().clone()).unwrap();
+        assert!(!output.contains("secret"));
+        let lines: Vec<serde_json::Value> = output
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        let error = lines.iter().find(|line| line["level"] == "ERROR").unwrap();
+        assert_eq!(error["span"]["method"], "GET");
+        assert_eq!(error["span"]["path"], "/v1/book");
+        assert!(error["span"]["request_id"].is_string());
+        assert!(
+            error["span"]["principal"]
+                .as_str()
+                .unwrap()
+                .starts_with("key:")
+        );
+        let chain = error["error.chain"].as_str().unwrap();
+        assert!(chain.contains("Could not list the books"));
+        assert!(chain.contains("Connection refused"));
+
+        let sent = lines.last().unwrap();
+        assert_eq!(sent["message"], "Response sent");
+        assert_eq!(sent["span"]["status"], 500);
+        assert!(sent["span"]["latency_ms"].is_u64());
+    }
+}
@@ -1,33 +1,150 @@
-use bookstore::{appstate::AppState, create_router, run_migrations};
-use color_eyre::eyre::Context;
-use sqlx::{Postgres, pool::PoolOptions};
-use std::{env, time::Duration};
-use tracing::info;
+use bookstore::{
+    cache::CacheOptions,
+    create_router,
+    database::Database,
+    logging::{LogFormat, setup_logging},
+    migrations::{MigrationMode, schema_is_current},
+    rate_limit::{
+        ClientResolver, InMemoryRateLimitStore, IpRange, PostgresRateLimitStore, RateLimitConfig,
+        RateLimitStore, RateLimiter,
+    },
+    repository::replica::DEFAULT_STICKINESS,
+};
+use color_eyre::eyre::{Context, bail};
+use std::{env, net::SocketAddr};
+use tracing::{info, warn};
+
+const USAGE: &str = "Usage:
+    bookstore                         start the web server
+    bookstore migrate up              apply every pending migration
+    bookstore migrate status          list applied and pending migrations
+    bookstore migrate rollback <N>    revert the last N applied migrations";
 
 #[tokio::main]
 async fn main() -> color_eyre::Result<()> {
     color_eyre::install().unwrap();
-    tracing_subscriber::fmt::init();
+    let log_format = match env::var("LOG_FORMAT") {
+        Ok(format) => format.parse()?,
+        Err(_) => LogFormat::default(),
+    };
+    setup_logging(log_format);
     let database_url = env::var("DATABASE_URL").expect("DATABASE_URL must be set");
-    let pool = PoolOptions::<Postgres>::new()
-        .max_connections(5)
-        .acquire_timeout(Duration::from_secs(3))
-        .connect(&database_url)
-        .await
-        .wrap_err("Could not connect to database")?;
-
-    {
-        let mut conn = pool.acquire().await?;
-        run_migrations(&mut conn).await?;
+    let database = Database::connect(&database_url).await?;
+
+    let args: Vec<String> = env::args().skip(1).collect();
+    let args: Vec<&str> = args.iter().map(String::as_str).collect();
+    match args.as_slice() {
+        [] => serve(database).await,
+        ["migrate", "up"] => Ok(database.run_migrations().await?),
+        ["migrate", "status"] => {
+            for status in database.migration_status().await? {
+                println!("{status}");
+            }
+            Ok(())
+        }
+        ["migrate", "rollback", steps] => {
+            let steps: usize = steps
+                .parse()
+                .wrap_err_with(|| format!("Invalid number of steps: {steps}"))?;
+            for version in database.rollback(steps).await? {
+                println!("Reverted {version}");
+            }
+            Ok(())
+        }
+        _ => bail!("Unknown command: {}\n{USAGE}", args.join(" ")),
     }
+}
 
-    let app = create_router(AppState::new(pool));
+async fn serve(database: Database) -> color_eyre::Result<()> {
+    let migration_mode = match env::var("MIGRATION_MODE") {
+        Ok(mode) => mode.parse()?,
+        Err(_) => MigrationMode::default(),
+    };
+    match migration_mode {
+        MigrationMode::Auto => database.run_migrations().await?,
+        MigrationMode::Verify => {
+            if !schema_is_current(&database.migration_status().await?) {
+                warn!(
+                    "The database schema is behind, not applying migrations in verify mode. Reporting not ready until it is migrated."
+                );
+            }
+        }
+    }
+
+    let mut app_state = database
+        .app_state()
+        .with_cache(CacheOptions::default())
+        .with_clients(clients()?);
+    if let (Some(cache), Database::Postgres(pool)) = (&app_state.cache, &database) {
+        cache.listen(pool.clone());
+    }
+    if let Ok(replica_url) = env::var("DATABASE_REPLICA_URL") {
+        let replica = Database::connect(&replica_url).await?;
+        info!("Sending reads to the replica");
+        app_state = app_state.with_replica(replica, DEFAULT_STICKINESS);
+    }
+    match env::var("RATE_LIMIT_STORE").as_deref() {
+        Ok("off") => info!("Rate limiting is off"),
+        Ok("postgres") => {
+            let Database::Postgres(pool) = &database else {
+                bail!("RATE_LIMIT_STORE=postgres needs a postgres database");
+            };
+            let store = PostgresRateLimitStore::new(pool.clone());
+            app_state = app_state.with_rate_limiter(rate_limiter(store)?);
+        }
+        Ok("memory") | Err(_) => {
+            let store = InMemoryRateLimitStore::new();
+            app_state = app_state.with_rate_limiter(rate_limiter(store)?);
+        }
+        Ok(other) => {
+            bail!("Unknown RATE_LIMIT_STORE '{other}', expected 'memory', 'postgres' or 'off'")
+        }
+    }
+    let app = create_router(app_state);
 
     let bindto = std::env::var("BIND_TO").unwrap_or("127.0.0.1:3000".to_string());
-    println!("Starting web server on {bindto}");
-    let addr = tokio::net::TcpListener::bind(bindto).await?;
+    info!(bindto, "Starting web server");
+    let listener = tokio::net::TcpListener::bind(bindto).await?;
+    // The port is picked by the system for port 0, so the address is only known now. A target of
+    // its own lets whoever reads it enable it, whatever else RUST_LOG filters out.
+    info!(target: "bookstore::listening", address = %listener.local_addr()?, "Listening");
 
-    axum::serve(addr, app).await?;
+    axum::serve(
+        listener,
+        app.into_make_service_with_connect_info::<SocketAddr>(),
+    )
+    .await?;
 
     Ok(())
 }
+
+fn rate_limiter(store: impl RateLimitStore + 'static) -> color_eyre::Result<RateLimiter> {
+    let mut config = RateLimitConfig::default();
+    if let Ok(read) = env::var("RATE_LIMIT_READ") {
+        config.read = read.parse()?;
+    }
+    if let Ok(write) = env::var("RATE_LIMIT_WRITE") {
+        config.write = write.parse()?;
+    }
+    Ok(RateLimiter::new(config, store))
+}
+
+/// Tells the clients apart by the API keys and behind the proxies of the configuration
+fn clients() -> color_eyre::Result<ClientResolver> {
+    let list = |name| {
+        env::var(name)
+            .unwrap_or_default()
+            .split(',')
+            .map(str::trim)
+            .filter(|item| !item.is_empty())
+            .map(String::from)
+            .collect::<Vec<_>>()
+    };
+    let trusted_proxies = list("RATE_LIMIT_TRUSTED_PROXIES")
+        .iter()
+        .map(|range| range.parse())
+        .collect::<color_eyre::Result<Vec<IpRange>>>()?;
+    Ok(ClientResolver::new()
+        .with_api_keys(list("RATE_LIMIT_API_KEYS"))
+        .with_trusted_proxies(trusted_proxies))
+}
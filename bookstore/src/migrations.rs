@@ -0,0 +1,172 @@
+use std::{collections::HashMap, fmt, str::FromStr};
+
+use color_eyre::eyre::bail;
+use sqlx::{
+    Database, Pool,
+    migrate::{Migrate, MigrateError, Migrator},
+};
+
+pub static POSTGRES_MIGRATOR: Migrator = sqlx::migrate!("./migrations");
+pub static SQLITE_MIGRATOR: Migrator = sqlx::migrate!("./migrations_sqlite");
+
+/// What to do with the schema when the web server starts
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum MigrationMode {
+    /// Apply every pending migration
+    #[default]
+    Auto,
+    /// Leave the schema alone, and report not ready for as long as it is behind. Meant for
+    /// production, where migrations are applied deliberately with `bookstore migrate up`.
+    Verify,
+}
+
+impl FromStr for MigrationMode {
+    type Err = color_eyre::Report;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "auto" => Ok(MigrationMode::Auto),
+            "verify" => Ok(MigrationMode::Verify),
+            other => bail!("Unknown migration mode '{other}', expected 'auto' or 'verify'"),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MigrationState {
+    Applied,
+    Pending,
+    /// Applied, but the migration file changed since
+    ChecksumMismatch,
+    /// Applied, but there is no such migration in this build, e.g. after deploying an older version
+    Unknown,
+}
+
+impl fmt::Display for MigrationState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let label = match self {
+            MigrationState::Applied => "applied",
+            MigrationState::Pending => "pending",
+            MigrationState::ChecksumMismatch => "checksum mismatch",
+            MigrationState::Unknown => "unknown",
+        };
+        f.pad(label)
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct MigrationStatus {
+    pub version: i64,
+    pub description: String,
+    /// Hex encoded checksum of the migration, the one recorded in the database for applied ones
+    pub checksum: String,
+    pub state: MigrationState,
+}
+
+impl fmt::Display for MigrationStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} {:<17} {} {}",
+            self.version, self.state, self.checksum, self.description
+        )
+    }
+}
+
+/// The schema is behind if there is anything it still has to apply, or if an applied migration
+/// doesn't match its file anymore
+pub fn schema_is_current(statuses: &[MigrationStatus]) -> bool {
+    statuses.iter().all(|status| {
+        !matches!(
+            status.state,
+            MigrationState::Pending | MigrationState::ChecksumMismatch
+        )
+    })
+}
+
+/// Status of every migration. Only reads the schema, so a database without the migrations table,
+/// which `table_exists` tells, has every migration pending, instead of getting the table created.
+pub async fn migration_status<DB>(
+    migrator: &Migrator,
+    pool: &Pool<DB>,
+    table_exists: bool,
+) -> Result<Vec<MigrationStatus>, MigrateError>
+where
+    DB: Database,
+    DB::Connection: Migrate,
+{
+    let mut applied: HashMap<i64, Vec<u8>> = HashMap::new();
+    if table_exists {
+        let mut conn = pool.acquire().await?;
+        applied.extend(
+            conn.list_applied_migrations()
+                .await?
+                .into_iter()
+                .map(|migration| (migration.version, migration.checksum.into_owned())),
+        );
+    }
+
+    let mut statuses: Vec<MigrationStatus> = migrator
+        .iter()
+        .filter(|migration| migration.migration_type.is_up_migration())
+        .map(|migration| {
+            let state = match applied.remove(&migration.version) {
+                Some(checksum) if checksum == *migration.checksum => MigrationState::Applied,
+                Some(_) => MigrationState::ChecksumMismatch,
+                None => MigrationState::Pending,
+            };
+            MigrationStatus {
+                version: migration.version,
+                description: migration.description.to_string(),
+                checksum: hex(&migration.checksum),
+                state,
+            }
+        })
+        .collect();
+    statuses.extend(
+        applied
+            .into_iter()
+            .map(|(version, checksum)| MigrationStatus {
+                version,
+                description: String::new(),
+                checksum: hex(&checksum),
+                state: MigrationState::Unknown,
+            }),
+    );
+    statuses.sort_by_key(|status| status.version);
+
+    Ok(statuses)
+}
+
+/// Reverts the last `steps` applied migrations, returning the versions that got reverted
+pub async fn rollback<DB>(
+    migrator: &Migrator,
+    pool: &Pool<DB>,
+    steps: usize,
+) -> Result<Vec<i64>, MigrateError>
+where
+    DB: Database,
+    DB::Connection: Migrate,
+{
+    let mut applied: Vec<i64> = {
+        let mut conn = pool.acquire().await?;
+        conn.ensure_migrations_table().await?;
+        conn.list_applied_migrations()
+            .await?
+            .into_iter()
+            .map(|migration| migration.version)
+            .collect()
+    };
+    applied.sort_unstable_by(|a, b| b.cmp(a));
+
+    // undo() reverts everything newer than the target, so the target is the newest migration
+    // that stays
+    let target = applied.get(steps).copied().unwrap_or(0);
+    migrator.undo(pool, target).await?;
+
+    Ok(applied.into_iter().take(steps).collect())
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{byte:02x}")).collect()
+}
@@ -0,0 +1,128 @@
+use utoipa::{
+    OpenApi,
+    openapi::{
+        ContentBuilder, Ref, RefOr, ResponseBuilder,
+        header::HeaderBuilder,
+        schema::{ObjectBuilder, Type},
+    },
+};
+use utoipa_axum::{router::OpenApiRouter, routes};
+
+use crate::{
+    appstate::AppState,
+    handlers,
+    versioning::{self, ApiVersion, Deprecation},
+};
+
+#[derive(OpenApi)]
+#[openapi(info(title = "Bookstore", description = "Registering and browsing books"))]
+struct ApiDoc;
+
+/// Every version of the API that is currently served
+pub const API_VERSIONS: &[ApiVersion] = &[
+    ApiVersion {
+        name: "v1",
+        prefix: "/v1",
+        routes: v1_routes,
+        deprecation: None,
+    },
+    // The routes from before versioning was introduced, kept around until clients move to /v1
+    ApiVersion {
+        name: "unversioned",
+        prefix: "",
+        routes: v1_routes,
+        deprecation: Some(Deprecation {
+            // 2026-10-19
+            since: 1792368000,
+            // 2027-04-19
+            sunset: Some(1808092800),
+        }),
+    },
+];
+
+pub fn v1_routes() -> OpenApiRouter<AppState> {
+    OpenApiRouter::new()
+        .routes(routes!(handlers::list_books, handlers::register_new_book))
+        .routes(routes!(handlers::show_book))
+}
+
+/// Every documented route of the API. The routes and the OpenAPI document are built from the same
+/// list, so a handler can't be mounted without showing up in the spec.
+pub fn api_router() -> OpenApiRouter<AppState> {
+    let mut doc = ApiDoc::openapi();
+    // utoipa fills this in from Cargo.toml, which has no license, leaving an empty one behind
+    doc.info.license = None;
+    let mut router = OpenApiRouter::with_openapi(doc).merge(versioning::mount(API_VERSIONS));
+    document_rate_limit(router.get_openapi_mut());
+    router
+}
+
+/// The rate limit middleware wraps every route, so every operation can answer with 429, which
+/// the handlers know nothing about
+fn document_rate_limit(openapi: &mut utoipa::openapi::OpenApi) {
+    const NAME: &str = "TooManyRequests";
+    let integer = |description: &str| {
+        HeaderBuilder::new()
+            .schema(
+                ObjectBuilder::new()
+                    .schema_type(Type::Integer)
+                    .minimum(Some(0)),
+            )
+            .description(Some(description))
+            .build()
+    };
+    let response = ResponseBuilder::new()
+        .description("The client sent too many requests of this kind, when rate limiting is on")
+        .header(
+            "Retry-After",
+            integer("Seconds until the request can be retried"),
+        )
+        .header(
+            "RateLimit-Limit",
+            integer("Requests of this kind allowed in a burst"),
+        )
+        .header(
+            "RateLimit-Remaining",
+            integer("Requests of this kind left right now"),
+        )
+        .header(
+            "RateLimit-Reset",
+            integer("Seconds until the quota is full again"),
+        )
+        .content(
+            "application/problem+json",
+            ContentBuilder::new()
+                .schema(Some(Ref::from_schema_name("ProblemDetails")))
+                .build(),
+        )
+        .build();
+    openapi
+        .components
+        .get_or_insert_with(Default::default)
+        .responses
+        .insert(NAME.to_string(), RefOr::T(response));
+
+    for item in openapi.paths.paths.values_mut() {
+        let operations = [
+            &mut item.get,
+            &mut item.put,
+            &mut item.post,
+            &mut item.delete,
+            &mut item.options,
+            &mut item.head,
+            &mut item.patch,
+            &mut item.trace,
+        ];
+        for operation in operations.into_iter().flatten() {
+            operation
+                .responses
+                .responses
+                .insert("429".to_string(), RefOr::Ref(Ref::from_response_name(NAME)));
+        }
+    }
+}
+
+pub fn openapi() -> utoipa::openapi::OpenApi {
+    let (_, api) = api_router().split_for_parts();
+    api
+}
@@ -0,0 +1,235 @@
+use std::{
+    collections::HashSet,
+    net::{IpAddr, SocketAddr},
+    str::FromStr,
+    sync::Arc,
+};
+
+use axum::{
+    extract::{ConnectInfo, Request},
+    http::HeaderMap,
+};
+use color_eyre::eyre::{Context, bail};
+use sha2::{Digest, Sha256};
+
+/// Tells the clients of the requests apart, by their API key if it is a known one, otherwise by
+/// their address. Any other key is ignored, so clients can neither pass as someone else nor get a
+/// new identity by making up keys.
+#[derive(Clone, Debug, Default)]
+pub struct ClientResolver {
+    api_keys: Arc<HashSet<String>>,
+    /// The proxies whose `X-Forwarded-For` tells the address of the client
+    trusted_proxies: Arc<[IpRange]>,
+}
+
+impl ClientResolver {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_api_keys(self, api_keys: impl IntoIterator<Item = String>) -> Self {
+        Self {
+            api_keys: Arc::new(api_keys.into_iter().collect()),
+            ..self
+        }
+    }
+
+    pub fn with_trusted_proxies(self, trusted_proxies: impl IntoIterator<Item = IpRange>) -> Self {
+        Self {
+            trusted_proxies: trusted_proxies.into_iter().collect(),
+            ..self
+        }
+    }
+
+    /// The client of `req`, as `key:<digest of the API key>` or `ip:<address>`, which can be
+    /// logged and stored without giving the key away. None without a known key or an address,
+    /// which only happens when the router is called directly instead of being served, e.g. in
+    /// tests.
+    pub fn client(&self, req: &Request) -> Option<String> {
+        let api_key = req
+            .headers()
+            .get("x-api-key")
+            .and_then(|key| key.to_str().ok())
+            .filter(|key| self.api_keys.contains(*key));
+        if let Some(key) = api_key {
+            let digest: String = Sha256::digest(key.as_bytes())[..8]
+                .iter()
+                .map(|byte| format!("{byte:02x}"))
+                .collect();
+            return Some(format!("key:{digest}"));
+        }
+        req.extensions()
+            .get::<ConnectInfo<SocketAddr>>()
+            .map(|ConnectInfo(peer)| {
+                let ip = client_ip(peer.ip(), req.headers(), &self.trusted_proxies);
+                format!("ip:{ip}")
+            })
+    }
+}
+
+/// A range of addresses, like `10.0.0.0/8`, or a single address
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct IpRange {
+    network: IpAddr,
+    prefix: u8,
+}
+
+impl IpRange {
+    pub fn contains(&self, ip: IpAddr) -> bool {
+        match (self.network, ip.to_canonical()) {
+            (IpAddr::V4(network), IpAddr::V4(ip)) => {
+                let mask = u32::MAX
+                    .checked_shl(32 - u32::from(self.prefix))
+                    .unwrap_or(0);
+                u32::from(network) & mask == u32::from(ip) & mask
+            }
+            (IpAddr::V6(network), IpAddr::V6(ip)) => {
+                let mask = u128::MAX
+                    .checked_shl(128 - u32::from(self.prefix))
+                    .unwrap_or(0);
+                u128::from(network) & mask == u128::from(ip) & mask
+            }
+            _ => false,
+        }
+    }
+}
+
+impl FromStr for IpRange {
+    type Err = color_eyre::Report;
+
+    /// Parses `<address>/<prefix length>`, or an address alone
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (address, prefix) = match s.trim().split_once('/') {
+            Some((address, prefix)) => (address, Some(prefix)),
+            None => (s.trim(), None),
+        };
+        let network = address
+            .parse::<IpAddr>()
+            .wrap_err_with(|| format!("Invalid address in '{s}'"))?
+            .to_canonical();
+        let max = if network.is_ipv4() { 32 } else { 128 };
+        let prefix = match prefix {
+            Some(prefix) => prefix
+                .parse()
+                .wrap_err_with(|| format!("Invalid prefix length in '{s}'"))?,
+            None => max,
+        };
+        if prefix > max {
+            bail!("Invalid prefix length in '{s}', it can be {max} at most");
+        }
+        Ok(IpRange { network, prefix })
+    }
+}
+
+/// Address of the client that sent the request to `peer`. Only a trusted proxy is believed about
+/// whom it forwards for: `X-Forwarded-For` is read from the right, and the first address that isn't
+/// a trusted proxy is the client. Anything left of it could have been made up by the client.
+pub fn client_ip(peer: IpAddr, headers: &HeaderMap, trusted_proxies: &[IpRange]) -> IpAddr {
+    let trusted = |ip: IpAddr| trusted_proxies.iter().any(|range| range.contains(ip));
+    let mut client = peer.to_canonical();
+    if !trusted(client) {
+        return client;
+    }
+    let forwarded: Vec<&str> = headers
+        .get_all("x-forwarded-for")
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .collect();
+    for hop in forwarded.iter().rev() {
+        // A proxy that passes on something else isn't worth believing any further
+        let Ok(ip) = hop.trim().parse::<IpAddr>() else {
+            break;
+        };
+        client = ip.to_canonical();
+        if !trusted(client) {
+            break;
+        }
+    }
+    client
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::http::HeaderValue;
+
+    use super::*;
+
+    fn ip(ip: &str) -> IpAddr {
+        ip.parse().unwrap()
+    }
+
+    fn forwarded_for(values: &[&'static str]) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        for value in values {
+            headers.append("x-forwarded-for", HeaderValue::from_static(value));
+        }
+        headers
+    }
+
+    #[test]
+    fn client_by_known_key_or_address() {
+        let clients = ClientResolver::new().with_api_keys([String::from("secret")]);
+        let request = |key: &str| {
+            let peer: SocketAddr = "203.0.113.7:40000".parse().unwrap();
+            Request::get("/")
+                .header("x-api-key", key)
+                .extension(ConnectInfo(peer))
+                .body(axum::body::Body::empty())
+                .unwrap()
+        };
+        let known = clients.client(&request("secret")).unwrap();
+        assert!(known.starts_with("key:"));
+        assert!(!known.contains("secret"));
+        assert_eq!(
+            clients.client(&request("made-up")).as_deref(),
+            Some("ip:203.0.113.7")
+        );
+    }
+
+    #[test]
+    fn ip_range_contains() {
+        let range: IpRange = "10.0.0.0/8".parse().unwrap();
+        assert!(range.contains(ip("10.20.30.40")));
+        assert!(range.contains(ip("::ffff:10.0.0.1")));
+        assert!(!range.contains(ip("11.0.0.1")));
+
+        let single: IpRange = "192.0.2.1".parse().unwrap();
+        assert!(single.contains(ip("192.0.2.1")));
+        assert!(!single.contains(ip("192.0.2.2")));
+
+        let everything: IpRange = "::/0".parse().unwrap();
+        assert!(everything.contains(ip("2001:db8::1")));
+        assert!("10.0.0.0/33".parse::<IpRange>().is_err());
+    }
+
+    #[test]
+    fn forwarded_for_ignored_from_untrusted_peer() {
+        let proxies = ["10.0.0.0/8".parse().unwrap()];
+        let headers = forwarded_for(&["198.51.100.1"]);
+        assert_eq!(
+            client_ip(ip("203.0.113.7"), &headers, &proxies),
+            ip("203.0.113.7")
+        );
+    }
+
+    #[test]
+    fn forwarded_for_read_from_the_right() {
+        let proxies = ["10.0.0.0/8".parse().unwrap()];
+        // The client made up the first address, the proxies added the others
+        let headers = forwarded_for(&["198.51.100.1, 203.0.113.7", "10.0.0.2"]);
+        assert_eq!(
+            client_ip(ip("10.0.0.1"), &headers, &proxies),
+            ip("203.0.113.7")
+        );
+        assert_eq!(
+            client_ip(ip("10.0.0.1"), &HeaderMap::new(), &proxies),
+            ip("10.0.0.1")
+        );
+        let headers = forwarded_for(&["203.0.113.7, unknown"]);
+        assert_eq!(
+            client_ip(ip("10.0.0.1"), &headers, &proxies),
+            ip("10.0.0.1")
+        );
+    }
+}
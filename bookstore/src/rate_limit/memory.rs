@@ -0,0 +1,112 @@
+use std::{collections::HashMap, sync::Mutex, time::Instant};
+
+use async_trait::async_trait;
+
+use super::{Decision, Quota, RateLimitStore};
+
+/// Buckets are dropped once there are this many, if they are full again, since a full bucket is
+/// the same as no bucket
+const PRUNE_THRESHOLD: usize = 10_000;
+
+#[derive(Debug)]
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+    /// When the bucket is full again, if it isn't taken from until then
+    full_at: Instant,
+}
+
+#[derive(Debug)]
+struct Buckets {
+    buckets: HashMap<String, Bucket>,
+    /// Pruning goes through every bucket, so it only happens again once there are twice as many
+    /// as were left after the last time. That spreads its cost over the buckets added in between.
+    prune_at: usize,
+}
+
+impl Default for Buckets {
+    fn default() -> Self {
+        Self {
+            buckets: HashMap::new(),
+            prune_at: PRUNE_THRESHOLD,
+        }
+    }
+}
+
+/// Keeps the buckets of this instance only, so every instance lets through the whole quota
+#[derive(Debug, Default)]
+pub struct InMemoryRateLimitStore {
+    buckets: Mutex<Buckets>,
+}
+
+impl InMemoryRateLimitStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+#[async_trait]
+impl RateLimitStore for InMemoryRateLimitStore {
+    async fn acquire(&self, key: &str, quota: Quota) -> color_eyre::Result<Decision> {
+        let now = Instant::now();
+        let burst = f64::from(quota.burst);
+        let refill = |bucket: &Bucket| {
+            let elapsed = now.duration_since(bucket.updated).as_secs_f64();
+            (bucket.tokens + elapsed * quota.refill_rate()).min(burst)
+        };
+
+        let mut guard = self.buckets.lock().unwrap();
+        let Buckets { buckets, prune_at } = &mut *guard;
+        if buckets.len() >= *prune_at {
+            // Every bucket knows when it's full, whatever quota it has
+            buckets.retain(|_, bucket| bucket.full_at > now);
+            *prune_at = (buckets.len() * 2).max(PRUNE_THRESHOLD);
+        }
+        let bucket = buckets.entry(key.to_string()).or_insert(Bucket {
+            tokens: burst,
+            updated: now,
+            full_at: now,
+        });
+        bucket.tokens = refill(bucket);
+        bucket.updated = now;
+        let allowed = bucket.tokens >= 1.0;
+        if allowed {
+            bucket.tokens -= 1.0;
+        }
+        let decision = Decision::from_tokens(quota, bucket.tokens, allowed);
+        bucket.full_at = now + decision.reset;
+
+        Ok(decision)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    const QUOTA: Quota = Quota {
+        burst: 1,
+        period: Duration::from_secs(3600),
+    };
+
+    #[tokio::test]
+    async fn pruning_keeps_buckets_in_use() {
+        let store = InMemoryRateLimitStore::new();
+        for i in 0..=PRUNE_THRESHOLD {
+            assert!(
+                store
+                    .acquire(&format!("ip:{i}"), QUOTA)
+                    .await
+                    .unwrap()
+                    .allowed
+            );
+        }
+        // None of the buckets was full, so none was dropped, and the next pruning waits
+        assert!(!store.acquire("ip:0", QUOTA).await.unwrap().allowed);
+        let buckets = store.buckets.lock().unwrap();
+        assert_eq!(buckets.buckets.len(), PRUNE_THRESHOLD + 1);
+        assert_eq!(buckets.prune_at, 2 * PRUNE_THRESHOLD);
+    }
+}
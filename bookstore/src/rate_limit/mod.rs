@@ -0,0 +1,201 @@
+pub mod client;
+pub mod memory;
+pub mod postgres;
+
+use std::{fmt, str::FromStr, sync::Arc, time::Duration};
+
+use async_trait::async_trait;
+use axum::{
+    extract::{Request, State},
+    http::{HeaderMap, HeaderValue, Method, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use color_eyre::eyre::{Context, bail};
+use tracing::warn;
+
+use crate::{
+    appstate::AppState,
+    util::{AxumHandlerError, current_client},
+};
+
+pub use client::{ClientResolver, IpRange};
+pub use memory::InMemoryRateLimitStore;
+pub use postgres::PostgresRateLimitStore;
+
+/// Routes that are never limited, so probes and scrapers keep working under load
+const UNLIMITED_PATHS: &[&str] = &["/ready", "/metrics"];
+
+/// `burst` requests per `period`. The bucket refills evenly over the period, so a client that used
+/// up its burst gets a new request every `period / burst`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Quota {
+    pub burst: u32,
+    pub period: Duration,
+}
+
+impl Quota {
+    /// Tokens added to the bucket per second
+    pub fn refill_rate(&self) -> f64 {
+        f64::from(self.burst) / self.period.as_secs_f64()
+    }
+}
+
+impl FromStr for Quota {
+    type Err = color_eyre::Report;
+
+    /// Parses `<requests>/<seconds>`, e.g. `100/60`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let Some((burst, seconds)) = s.split_once('/') else {
+            bail!("Invalid rate limit '{s}', expected <requests>/<seconds>");
+        };
+        let burst: u32 = burst
+            .trim()
+            .parse()
+            .wrap_err_with(|| format!("Invalid number of requests in rate limit '{s}'"))?;
+        let seconds: u64 = seconds
+            .trim()
+            .parse()
+            .wrap_err_with(|| format!("Invalid number of seconds in rate limit '{s}'"))?;
+        if burst == 0 || seconds == 0 {
+            bail!("Invalid rate limit '{s}', neither the requests nor the seconds can be 0");
+        }
+        Ok(Quota {
+            burst,
+            period: Duration::from_secs(seconds),
+        })
+    }
+}
+
+/// What a bucket said about a request
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Decision {
+    pub allowed: bool,
+    pub limit: u32,
+    /// Requests the client can still make right away
+    pub remaining: u32,
+    /// Until the bucket is full again
+    pub reset: Duration,
+    /// Until the next request is let through, if this one wasn't
+    pub retry_after: Option<Duration>,
+}
+
+impl Decision {
+    /// The decision for a bucket left with `tokens` after taking from it, if `allowed`
+    pub fn from_tokens(quota: Quota, tokens: f64, allowed: bool) -> Self {
+        let rate = quota.refill_rate();
+        let tokens = tokens.clamp(0.0, f64::from(quota.burst));
+        Decision {
+            allowed,
+            limit: quota.burst,
+            remaining: tokens.floor() as u32,
+            reset: Duration::from_secs_f64((f64::from(quota.burst) - tokens) / rate),
+            retry_after: (!allowed).then(|| Duration::from_secs_f64((1.0 - tokens) / rate)),
+        }
+    }
+}
+
+/// Keeps the token buckets
+#[async_trait]
+pub trait RateLimitStore: Send + Sync + fmt::Debug {
+    /// Takes a token from the bucket of `key`, if there is one
+    async fn acquire(&self, key: &str, quota: Quota) -> color_eyre::Result<Decision>;
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct RateLimitConfig {
+    /// Quota of the safe methods, like GET
+    pub read: Quota,
+    /// Quota of the methods changing something, like `POST /book`
+    pub write: Quota,
+}
+
+impl Default for RateLimitConfig {
+    fn default() -> Self {
+        Self {
+            read: Quota {
+                burst: 300,
+                period: Duration::from_secs(60),
+            },
+            write: Quota {
+                burst: 20,
+                period: Duration::from_secs(60),
+            },
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct RateLimiter {
+    config: RateLimitConfig,
+    store: Arc<dyn RateLimitStore>,
+}
+
+impl RateLimiter {
+    pub fn new(config: RateLimitConfig, store: impl RateLimitStore + 'static) -> Self {
+        Self {
+            config,
+            store: Arc::new(store),
+        }
+    }
+}
+
+/// Limits every client per kind of route, answering 429 Too Many Requests once the client used up
+/// its quota. Clients are told apart the way the [`ClientResolver`] of the state tells them apart.
+pub async fn rate_limit_mw(State(state): State<AppState>, req: Request, next: Next) -> Response {
+    let Some(limiter) = &state.rate_limiter else {
+        return next.run(req).await;
+    };
+    if UNLIMITED_PATHS.contains(&req.uri().path()) {
+        return next.run(req).await;
+    }
+    // Without a key or an address there is nothing to tell the client apart by. That only happens
+    // when the router is called directly instead of being served, e.g. in tests.
+    let Some(client) = current_client() else {
+        return next.run(req).await;
+    };
+    let (class, quota) = if is_write(req.method()) {
+        ("write", limiter.config.write)
+    } else {
+        ("read", limiter.config.read)
+    };
+
+    let decision = match limiter
+        .store
+        .acquire(&format!("{class}:{client}"), quota)
+        .await
+    {
+        Ok(decision) => decision,
+        Err(e) => {
+            // Better to let everyone through than no one
+            warn!("Could not check the rate limit, letting the request through: {e:?}");
+            return next.run(req).await;
+        }
+    };
+    let mut response = if decision.allowed {
+        next.run(req).await
+    } else {
+        AxumHandlerError::Rejected {
+            status: StatusCode::TOO_MANY_REQUESTS,
+            msg: "Too many requests, try again later".to_string(),
+        }
+        .into_response()
+    };
+    set_headers(response.headers_mut(), &decision);
+    response
+}
+
+fn is_write(method: &Method) -> bool {
+    !matches!(*method, Method::GET | Method::HEAD | Method::OPTIONS)
+}
+
+/// The `RateLimit-*` headers of the IETF draft, and `Retry-After` for rejected requests
+fn set_headers(headers: &mut HeaderMap, decision: &Decision) {
+    let seconds = |duration: Duration| HeaderValue::from(duration.as_secs_f64().ceil() as u64);
+    headers.insert("ratelimit-limit", HeaderValue::from(decision.limit));
+    headers.insert("ratelimit-remaining", HeaderValue::from(decision.remaining));
+    headers.insert("ratelimit-reset", seconds(decision.reset));
+    if let Some(retry_after) = decision.retry_after {
+        headers.insert("retry-after", seconds(retry_after));
+    }
+}
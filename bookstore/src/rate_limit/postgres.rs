@@ -0,0 +1,64 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use async_trait::async_trait;
+use sqlx::{Pool, Postgres};
+
+use super::{Decision, Quota, RateLimitStore};
+
+/// Every this many requests, buckets untouched for a day are deleted
+const PRUNE_EVERY: u64 = 1000;
+
+/// Keeps the buckets in postgres, so the limits hold across every instance using the same database
+#[derive(Debug)]
+pub struct PostgresRateLimitStore {
+    pool: Pool<Postgres>,
+    requests: AtomicU64,
+}
+
+impl PostgresRateLimitStore {
+    pub fn new(pool: Pool<Postgres>) -> Self {
+        Self {
+            pool,
+            requests: AtomicU64::new(0),
+        }
+    }
+}
+
+#[async_trait]
+impl RateLimitStore for PostgresRateLimitStore {
+    async fn acquire(&self, key: &str, quota: Quota) -> color_eyre::Result<Decision> {
+        if self.requests.fetch_add(1, Ordering::Relaxed) % PRUNE_EVERY == PRUNE_EVERY - 1 {
+            sqlx::query!(
+                "DELETE FROM rate_limit_bucket WHERE updated_at < now() - interval '1 day'"
+            )
+            .execute(&self.pool)
+            .await?;
+        }
+
+        // Refilling and taking a token happens in a single statement, so concurrent requests of
+        // the same client, even on different instances, can't take the same token
+        let bucket = sqlx::query!(
+            "INSERT INTO rate_limit_bucket AS bucket (key, tokens, allowed, updated_at)
+            VALUES ($1, $2::float8 - 1, TRUE, now())
+            ON CONFLICT (key) DO UPDATE SET
+                allowed = LEAST(
+                    $2, bucket.tokens + EXTRACT(EPOCH FROM now() - bucket.updated_at)::float8 * $3
+                ) >= 1,
+                -- Takes the token only if there is a whole one
+                tokens = LEAST(
+                    $2, bucket.tokens + EXTRACT(EPOCH FROM now() - bucket.updated_at)::float8 * $3
+                ) - LEAST(1, FLOOR(LEAST(
+                    $2, bucket.tokens + EXTRACT(EPOCH FROM now() - bucket.updated_at)::float8 * $3
+                ))),
+                updated_at = now()
+            RETURNING tokens, allowed",
+            key,
+            f64::from(quota.burst),
+            quota.refill_rate(),
+        )
+        .fetch_one(&self.pool)
+        .await?;
+
+        Ok(Decision::from_tokens(quota, bucket.tokens, bucket.allowed))
+    }
+}
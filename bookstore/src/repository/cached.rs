@@ -0,0 +1,63 @@
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use uuid::Uuid;
+
+use super::BookRepository;
+use crate::{
+    appstate::Book,
+    cache::{BookCache, Source},
+    handlers::BookRegistration,
+};
+
+/// Answers reads from the cache when it can, and invalidates it on writes
+#[derive(Debug)]
+pub struct CachedBookRepository {
+    inner: Arc<dyn BookRepository>,
+    cache: BookCache,
+    source: Source,
+}
+
+impl CachedBookRepository {
+    pub fn new(inner: Arc<dyn BookRepository>, cache: BookCache, source: Source) -> Self {
+        Self {
+            inner,
+            cache,
+            source,
+        }
+    }
+}
+
+#[async_trait]
+impl BookRepository for CachedBookRepository {
+    async fn register_book(&self, book: &BookRegistration) -> color_eyre::Result<Option<Book>> {
+        let registered = self.inner.register_book(book).await?;
+        if let Some(book) = &registered {
+            self.cache.invalidate_book(book.id);
+        }
+        Ok(registered)
+    }
+
+    async fn get_book_by_id(&self, id: Uuid) -> color_eyre::Result<Option<Book>> {
+        if let Some(book) = self.cache.book(self.source, id) {
+            return Ok(Some(book));
+        }
+        let generation = self.cache.generation();
+        let book = self.inner.get_book_by_id(id).await?;
+        // Missing books aren't cached, they are not asked for often enough to be worth it
+        if let Some(book) = &book {
+            self.cache.put_book(self.source, book.clone(), generation);
+        }
+        Ok(book)
+    }
+
+    async fn list_books(&self) -> color_eyre::Result<Vec<Book>> {
+        if let Some(books) = self.cache.list(self.source) {
+            return Ok(books);
+        }
+        let generation = self.cache.generation();
+        let books = self.inner.list_books().await?;
+        self.cache.put_list(self.source, books.clone(), generation);
+        Ok(books)
+    }
+}
@@ -0,0 +1,219 @@
+use std::sync::{Arc, Mutex};
+
+use async_trait::async_trait;
+use chrono::Utc;
+use uuid::Uuid;
+
+use super::BookRepository;
+use crate::{
+    appstate::Book,
+    handlers::BookRegistration,
+    unit_of_work::{
+        IsolationLevel, PendingTransaction, SerializationFailure, TransactionManager, UnitOfWork,
+    },
+};
+
+#[derive(Clone, Debug, Default)]
+struct MemoryState {
+    // A Vec keeps the insertion order, so listing is stable
+    books: Vec<Book>,
+    /// Bumped on every write, so transactions can tell if someone else wrote in the meantime
+    version: u64,
+}
+
+/// Keeps books in memory, for tests that don't need a database, and for trying out the API
+#[derive(Clone, Debug, Default)]
+pub struct InMemoryBookRepository {
+    state: Arc<Mutex<MemoryState>>,
+}
+
+impl InMemoryBookRepository {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Transactions over the books of this repository
+    pub fn transactions(&self) -> InMemoryTransactions {
+        InMemoryTransactions {
+            state: Arc::clone(&self.state),
+        }
+    }
+}
+
+#[async_trait]
+impl BookRepository for InMemoryBookRepository {
+    async fn register_book(&self, book: &BookRegistration) -> color_eyre::Result<Option<Book>> {
+        let mut state = self.state.lock().unwrap();
+        if state
+            .books
+            .iter()
+            .any(|existing| existing.name == book.name)
+        {
+            return Ok(None);
+        }
+        let book = Book {
+            id: Uuid::new_v4(),
+            name: book.name.clone(),
+            description: book.description.clone(),
+            updated_at: Utc::now(),
+        };
+        state.books.push(book.clone());
+        state.version += 1;
+
+        Ok(Some(book))
+    }
+
+    async fn get_book_by_id(&self, id: Uuid) -> color_eyre::Result<Option<Book>> {
+        let state = self.state.lock().unwrap();
+        Ok(state.books.iter().find(|book| book.id == id).cloned())
+    }
+
+    async fn list_books(&self) -> color_eyre::Result<Vec<Book>> {
+        Ok(self.state.lock().unwrap().books.clone())
+    }
+}
+
+/// Transactions work on a copy of the books, which replaces the original on commit. If anyone
+/// else wrote in the meantime, committing fails with a [`SerializationFailure`], so every
+/// transaction behaves as if it was serializable.
+#[derive(Clone, Debug)]
+pub struct InMemoryTransactions {
+    state: Arc<Mutex<MemoryState>>,
+}
+
+#[async_trait]
+impl TransactionManager for InMemoryTransactions {
+    async fn begin(
+        &self,
+        _isolation: IsolationLevel,
+    ) -> color_eyre::Result<(UnitOfWork, Box<dyn PendingTransaction>)> {
+        // Serializable, which satisfies every level, see the type
+        let snapshot = self.state.lock().unwrap().clone();
+        let base_version = snapshot.version;
+        let working = InMemoryBookRepository {
+            state: Arc::new(Mutex::new(snapshot)),
+        };
+        let pending = InMemoryTransaction {
+            state: Arc::clone(&self.state),
+            working: Arc::clone(&working.state),
+            base_version,
+        };
+        let unit_of_work = UnitOfWork {
+            books: Arc::new(working),
+        };
+        Ok((unit_of_work, Box::new(pending)))
+    }
+}
+
+struct InMemoryTransaction {
+    state: Arc<Mutex<MemoryState>>,
+    working: Arc<Mutex<MemoryState>>,
+    base_version: u64,
+}
+
+#[async_trait]
+impl PendingTransaction for InMemoryTransaction {
+    async fn commit(self: Box<Self>) -> color_eyre::Result<()> {
+        let working = self.working.lock().unwrap();
+        if working.version == self.base_version {
+            // Nothing was written, there is nothing to conflict with
+            return Ok(());
+        }
+        let mut state = self.state.lock().unwrap();
+        if state.version != self.base_version {
+            return Err(SerializationFailure.into());
+        }
+        *state = MemoryState {
+            books: working.books.clone(),
+            version: state.version + 1,
+        };
+        Ok(())
+    }
+
+    async fn rollback(self: Box<Self>) -> color_eyre::Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn registration(name: &str) -> BookRegistration {
+        BookRegistration {
+            name: name.to_string(),
+            description: String::from("A book"),
+        }
+    }
+
+    #[tokio::test]
+    async fn register_and_get() -> color_eyre::Result<()> {
+        let books = InMemoryBookRepository::new();
+        let registered = books
+            .register_book(&registration("Ship of Theseus"))
+            .await?
+            .expect("The name is free");
+        let found = books.get_book_by_id(registered.id).await?;
+        assert_eq!(
+            found.map(|book| book.name).as_deref(),
+            Some("Ship of Theseus")
+        );
+        assert!(books.get_book_by_id(Uuid::new_v4()).await?.is_none());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn register_taken_name() -> color_eyre::Result<()> {
+        let books = InMemoryBookRepository::new();
+        books
+            .register_book(&registration("Ship of Theseus"))
+            .await?;
+        let again = books
+            .register_book(&registration("Ship of Theseus"))
+            .await?;
+        assert!(again.is_none());
+        assert_eq!(books.list_books().await?.len(), 1);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn list_in_insertion_order() -> color_eyre::Result<()> {
+        let books = InMemoryBookRepository::new();
+        for name in ["Ship of Theseus", "House of Leaves", "Gödel, Escher, Bach"] {
+            books.register_book(&registration(name)).await?;
+        }
+        let names: Vec<String> = books
+            .list_books()
+            .await?
+            .into_iter()
+            .map(|book| book.name)
+            .collect();
+        assert_eq!(
+            names,
+            ["Ship of Theseus", "House of Leaves", "Gödel, Escher, Bach"]
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn concurrent_transactions_conflict() -> color_eyre::Result<()> {
+        let books = InMemoryBookRepository::new();
+        let transactions = books.transactions();
+        let (first, first_pending) = transactions.begin(IsolationLevel::Serializable).await?;
+        let (second, second_pending) = transactions.begin(IsolationLevel::Serializable).await?;
+        first
+            .books
+            .register_book(&registration("Ship of Theseus"))
+            .await?;
+        second
+            .books
+            .register_book(&registration("House of Leaves"))
+            .await?;
+
+        first_pending.commit().await?;
+        let conflict = second_pending.commit().await.unwrap_err();
+        assert!(conflict.is::<SerializationFailure>());
+        assert_eq!(books.list_books().await?.len(), 1);
+        Ok(())
+    }
+}
@@ -0,0 +1,145 @@
+pub mod cached;
+pub mod memory;
+pub mod postgres;
+pub mod replica;
+pub mod sqlite;
+
+use std::{
+    fmt,
+    ops::{Deref, DerefMut},
+    sync::Arc,
+};
+
+use async_trait::async_trait;
+use color_eyre::eyre::{OptionExt, bail};
+use sqlx::{Database, Pool, Transaction, pool::PoolConnection};
+use tokio::sync::{Mutex, MutexGuard};
+use uuid::Uuid;
+
+use crate::{appstate::Book, handlers::BookRegistration, unit_of_work::PendingTransaction};
+
+pub use cached::CachedBookRepository;
+pub use memory::{InMemoryBookRepository, InMemoryTransactions};
+pub use postgres::{PostgresBookRepository, PostgresTransactions};
+pub use replica::ReplicatedBookRepository;
+pub use sqlite::{SqliteBookRepository, SqliteTransactions};
+
+/// Storage of books. Handlers only talk to storage through this, so they don't care which backend
+/// they are running against.
+#[async_trait]
+pub trait BookRepository: Send + Sync + std::fmt::Debug {
+    /// Registers the book, or returns `None` if a book with the same name already exists.
+    ///
+    /// Implementations have to make the check and the insert atomic, so concurrent registrations
+    /// of the same book can't both succeed.
+    async fn register_book(&self, book: &BookRegistration) -> color_eyre::Result<Option<Book>>;
+
+    async fn get_book_by_id(&self, id: Uuid) -> color_eyre::Result<Option<Book>>;
+
+    async fn list_books(&self) -> color_eyre::Result<Vec<Book>>;
+}
+
+type SharedTransaction<DB> = Arc<Mutex<Option<Transaction<'static, DB>>>>;
+
+/// Where the SQL repositories get their connections from: the pool, or an ongoing transaction
+/// shared by every repository of a unit of work
+pub enum Connections<DB: Database> {
+    Pool(Pool<DB>),
+    Transaction(SharedTransaction<DB>),
+}
+
+impl<DB: Database> Connections<DB> {
+    pub async fn acquire(&self) -> color_eyre::Result<ConnectionGuard<'_, DB>> {
+        match self {
+            Connections::Pool(pool) => Ok(ConnectionGuard::Pool(pool.acquire().await?)),
+            Connections::Transaction(transaction) => {
+                let guard = transaction.lock().await;
+                if guard.is_none() {
+                    bail!("The transaction has already finished");
+                }
+                Ok(ConnectionGuard::Transaction(guard))
+            }
+        }
+    }
+}
+
+impl<DB: Database> Clone for Connections<DB> {
+    fn clone(&self) -> Self {
+        match self {
+            Connections::Pool(pool) => Connections::Pool(pool.clone()),
+            Connections::Transaction(transaction) => {
+                Connections::Transaction(Arc::clone(transaction))
+            }
+        }
+    }
+}
+
+impl<DB: Database> fmt::Debug for Connections<DB> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Connections::Pool(pool) => f.debug_tuple("Pool").field(pool).finish(),
+            Connections::Transaction(_) => f.write_str("Transaction"),
+        }
+    }
+}
+
+pub enum ConnectionGuard<'a, DB: Database> {
+    Pool(PoolConnection<DB>),
+    Transaction(MutexGuard<'a, Option<Transaction<'static, DB>>>),
+}
+
+impl<DB: Database> Deref for ConnectionGuard<'_, DB> {
+    type Target = DB::Connection;
+
+    fn deref(&self) -> &Self::Target {
+        match self {
+            ConnectionGuard::Pool(conn) => conn,
+            // safety: Connections::acquire only hands out guards of ongoing transactions, and the
+            // transaction can't be finished while the guard holds the lock
+            ConnectionGuard::Transaction(guard) => guard.as_ref().unwrap(),
+        }
+    }
+}
+
+impl<DB: Database> DerefMut for ConnectionGuard<'_, DB> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        match self {
+            ConnectionGuard::Pool(conn) => conn,
+            // safety: see deref()
+            ConnectionGuard::Transaction(guard) => guard.as_mut().unwrap(),
+        }
+    }
+}
+
+/// Finishes a transaction shared through [`Connections::Transaction`]
+pub struct SqlTransaction<DB: Database>(SharedTransaction<DB>);
+
+impl<DB: Database> SqlTransaction<DB> {
+    /// Shares `transaction`, returning the connections for the repositories, and the handle to
+    /// finish it with
+    pub fn share(transaction: Transaction<'static, DB>) -> (Connections<DB>, Self) {
+        let shared = Arc::new(Mutex::new(Some(transaction)));
+        (Connections::Transaction(Arc::clone(&shared)), Self(shared))
+    }
+
+    async fn take(&self) -> color_eyre::Result<Transaction<'static, DB>> {
+        self.0
+            .lock()
+            .await
+            .take()
+            .ok_or_eyre("The transaction has already finished")
+    }
+}
+
+#[async_trait]
+impl<DB: Database> PendingTransaction for SqlTransaction<DB> {
+    async fn commit(self: Box<Self>) -> color_eyre::Result<()> {
+        self.take().await?.commit().await?;
+        Ok(())
+    }
+
+    async fn rollback(self: Box<Self>) -> color_eyre::Result<()> {
+        self.take().await?.rollback().await?;
+        Ok(())
+    }
+}
@@ -0,0 +1,107 @@
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use sqlx::{Executor, Pool, Postgres};
+use uuid::Uuid;
+
+use super::{BookRepository, Connections, SqlTransaction};
+use crate::{
+    appstate::Book,
+    handlers::BookRegistration,
+    unit_of_work::{IsolationLevel, PendingTransaction, TransactionManager, UnitOfWork},
+};
+
+#[derive(Clone, Debug)]
+pub struct PostgresBookRepository {
+    connections: Connections<Postgres>,
+}
+
+impl PostgresBookRepository {
+    pub fn new(pool: Pool<Postgres>) -> Self {
+        Self {
+            connections: Connections::Pool(pool),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct PostgresTransactions {
+    pool: Pool<Postgres>,
+}
+
+impl PostgresTransactions {
+    pub fn new(pool: Pool<Postgres>) -> Self {
+        Self { pool }
+    }
+}
+
+#[async_trait]
+impl BookRepository for PostgresBookRepository {
+    async fn register_book(&self, book: &BookRegistration) -> color_eyre::Result<Option<Book>> {
+        let mut conn = self.connections.acquire().await?;
+        let id = Uuid::new_v4();
+        // The unique constraint on the name does the existence check, so there is no window
+        // between checking and inserting
+        let book = sqlx::query_as!(
+            Book,
+            "INSERT INTO book (id, name, description) VALUES ($1, $2, $3)
+            ON CONFLICT (name) DO NOTHING
+            RETURNING id, name, description, updated_at",
+            id,
+            book.name,
+            book.description,
+        )
+        .fetch_optional(&mut *conn)
+        .await?;
+
+        Ok(book)
+    }
+
+    async fn get_book_by_id(&self, id: Uuid) -> color_eyre::Result<Option<Book>> {
+        let mut conn = self.connections.acquire().await?;
+        let book = sqlx::query_as!(
+            Book,
+            "SELECT id, name, description, updated_at FROM book WHERE id = $1",
+            id
+        )
+        .fetch_optional(&mut *conn)
+        .await?;
+
+        Ok(book)
+    }
+
+    async fn list_books(&self) -> color_eyre::Result<Vec<Book>> {
+        let mut conn = self.connections.acquire().await?;
+        let books = sqlx::query_as!(
+            Book,
+            "SELECT id, name, description, updated_at FROM book ORDER BY registration_order"
+        )
+        .fetch_all(&mut *conn)
+        .await?;
+
+        Ok(books)
+    }
+}
+
+#[async_trait]
+impl TransactionManager for PostgresTransactions {
+    async fn begin(
+        &self,
+        isolation: IsolationLevel,
+    ) -> color_eyre::Result<(UnitOfWork, Box<dyn PendingTransaction>)> {
+        let mut transaction = self.pool.begin().await?;
+        let statement = match isolation {
+            IsolationLevel::ReadCommitted => "SET TRANSACTION ISOLATION LEVEL READ COMMITTED",
+            IsolationLevel::RepeatableRead => "SET TRANSACTION ISOLATION LEVEL REPEATABLE READ",
+            IsolationLevel::Serializable => "SET TRANSACTION ISOLATION LEVEL SERIALIZABLE",
+        };
+        // SET can't be prepared, so it can't be a checked query either
+        (&mut *transaction).execute(statement).await?;
+
+        let (connections, pending) = SqlTransaction::share(transaction);
+        let unit_of_work = UnitOfWork {
+            books: Arc::new(PostgresBookRepository { connections }),
+        };
+        Ok((unit_of_work, Box::new(pending)))
+    }
+}
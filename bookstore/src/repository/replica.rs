@@ -0,0 +1,114 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use async_trait::async_trait;
+use futures::future::BoxFuture;
+use tracing::warn;
+use uuid::Uuid;
+
+use super::BookRepository;
+use crate::{appstate::Book, handlers::BookRegistration, util::current_client};
+
+/// How long a client keeps reading from the primary after writing, so it sees its own writes
+/// even if the replica lags behind
+pub const DEFAULT_STICKINESS: Duration = Duration::from_secs(5);
+
+/// How long the replica is skipped after it failed, instead of waiting on it for every read
+const REPLICA_RETRY_AFTER: Duration = Duration::from_secs(10);
+
+/// Sends writes to the primary, and reads to the replica. Reads fall back to the primary when the
+/// replica is unavailable, and go to the primary for a while after the same client wrote.
+#[derive(Debug)]
+pub struct ReplicatedBookRepository {
+    primary: Arc<dyn BookRepository>,
+    replica: Arc<dyn BookRepository>,
+    stickiness: Duration,
+    /// When each client wrote the last time. Only clients within the stickiness window are kept.
+    recent_writes: Mutex<HashMap<String, Instant>>,
+    replica_down_until: Mutex<Option<Instant>>,
+}
+
+impl ReplicatedBookRepository {
+    pub fn new(
+        primary: Arc<dyn BookRepository>,
+        replica: Arc<dyn BookRepository>,
+        stickiness: Duration,
+    ) -> Self {
+        Self {
+            primary,
+            replica,
+            stickiness,
+            recent_writes: Mutex::default(),
+            replica_down_until: Mutex::default(),
+        }
+    }
+
+    fn record_write(&self) {
+        let Some(client) = current_client() else {
+            return;
+        };
+        let now = Instant::now();
+        let mut recent_writes = self.recent_writes.lock().unwrap();
+        recent_writes.retain(|_, written| now.duration_since(*written) < self.stickiness);
+        recent_writes.insert(client, now);
+    }
+
+    /// Whether the current read can go to the replica
+    fn use_replica(&self) -> bool {
+        let now = Instant::now();
+        let wrote_recently = current_client().is_some_and(|client| {
+            self.recent_writes
+                .lock()
+                .unwrap()
+                .get(&client)
+                .is_some_and(|written| now.duration_since(*written) < self.stickiness)
+        });
+        let replica_down = self
+            .replica_down_until
+            .lock()
+            .unwrap()
+            .is_some_and(|until| now < until);
+        !wrote_recently && !replica_down
+    }
+
+    /// Runs `read` on the replica if it can, retrying it on the primary if the replica failed
+    async fn read<'a, T, F>(&'a self, read: F) -> color_eyre::Result<T>
+    where
+        F: Fn(&'a dyn BookRepository) -> BoxFuture<'a, color_eyre::Result<T>>,
+    {
+        if !self.use_replica() {
+            return read(self.primary.as_ref()).await;
+        }
+        match read(self.replica.as_ref()).await {
+            Ok(value) => Ok(value),
+            Err(e) => {
+                warn!("Replica is unavailable, reading from the primary: {e:?}");
+                *self.replica_down_until.lock().unwrap() =
+                    Some(Instant::now() + REPLICA_RETRY_AFTER);
+                read(self.primary.as_ref()).await
+            }
+        }
+    }
+}
+
+#[async_trait]
+impl BookRepository for ReplicatedBookRepository {
+    async fn register_book(&self, book: &BookRegistration) -> color_eyre::Result<Option<Book>> {
+        let registered = self.primary.register_book(book).await?;
+        if registered.is_some() {
+            self.record_write();
+        }
+        Ok(registered)
+    }
+
+    async fn get_book_by_id(&self, id: Uuid) -> color_eyre::Result<Option<Book>> {
+        self.read(|books| books.get_book_by_id(id)).await
+    }
+
+    async fn list_books(&self) -> color_eyre::Result<Vec<Book>> {
+        self.read(|books| books.list_books()).await
+    }
+}
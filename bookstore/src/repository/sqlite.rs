@@ -0,0 +1,104 @@
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use sqlx::{Pool, Sqlite};
+use uuid::Uuid;
+
+use super::{BookRepository, Connections, SqlTransaction};
+use crate::{
+    appstate::Book,
+    handlers::BookRegistration,
+    unit_of_work::{IsolationLevel, PendingTransaction, TransactionManager, UnitOfWork},
+};
+
+#[derive(Clone, Debug)]
+pub struct SqliteBookRepository {
+    connections: Connections<Sqlite>,
+}
+
+impl SqliteBookRepository {
+    pub fn new(pool: Pool<Sqlite>) -> Self {
+        Self {
+            connections: Connections::Pool(pool),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct SqliteTransactions {
+    pool: Pool<Sqlite>,
+}
+
+impl SqliteTransactions {
+    pub fn new(pool: Pool<Sqlite>) -> Self {
+        Self { pool }
+    }
+}
+
+#[async_trait]
+impl BookRepository for SqliteBookRepository {
+    async fn register_book(&self, book: &BookRegistration) -> color_eyre::Result<Option<Book>> {
+        let mut conn = self.connections.acquire().await?;
+        let id = Uuid::new_v4();
+        // The unique constraint on the name does the existence check, so there is no window
+        // between checking and inserting
+        let book = sqlx::query_as!(
+            Book,
+            r#"INSERT INTO book (id, name, description) VALUES (?1, ?2, ?3)
+            ON CONFLICT (name) DO NOTHING
+            RETURNING id AS "id: Uuid", name, description, updated_at AS "updated_at: DateTime<Utc>""#,
+            id,
+            book.name,
+            book.description,
+        )
+        .fetch_optional(&mut *conn)
+        .await?;
+
+        Ok(book)
+    }
+
+    async fn get_book_by_id(&self, id: Uuid) -> color_eyre::Result<Option<Book>> {
+        let mut conn = self.connections.acquire().await?;
+        let book = sqlx::query_as!(
+            Book,
+            r#"SELECT id AS "id: Uuid", name, description, updated_at AS "updated_at: DateTime<Utc>"
+            FROM book WHERE id = ?1"#,
+            id
+        )
+        .fetch_optional(&mut *conn)
+        .await?;
+
+        Ok(book)
+    }
+
+    async fn list_books(&self) -> color_eyre::Result<Vec<Book>> {
+        let mut conn = self.connections.acquire().await?;
+        let books = sqlx::query_as!(
+            Book,
+            r#"SELECT id AS "id: Uuid", name, description, updated_at AS "updated_at: DateTime<Utc>"
+            FROM book ORDER BY rowid"#
+        )
+        .fetch_all(&mut *conn)
+        .await?;
+
+        Ok(books)
+    }
+}
+
+#[async_trait]
+impl TransactionManager for SqliteTransactions {
+    async fn begin(
+        &self,
+        _isolation: IsolationLevel,
+    ) -> color_eyre::Result<(UnitOfWork, Box<dyn PendingTransaction>)> {
+        // SQLite transactions are always serializable, which satisfies every level
+        let transaction = self.pool.begin().await?;
+
+        let (connections, pending) = SqlTransaction::share(transaction);
+        let unit_of_work = UnitOfWork {
+            books: Arc::new(SqliteBookRepository { connections }),
+        };
+        Ok((unit_of_work, Box::new(pending)))
+    }
+}
@@ -0,0 +1,85 @@
+use std::{fmt, sync::Arc};
+
+use async_trait::async_trait;
+
+use crate::repository::BookRepository;
+
+/// The weakest isolation a transaction may run with. Backends are free to run a stronger one:
+/// Postgres runs the requested level, SQLite and the in-memory backend always run serializable
+/// transactions, which is at least as strong as any level.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum IsolationLevel {
+    #[default]
+    ReadCommitted,
+    RepeatableRead,
+    Serializable,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct TransactionOptions {
+    pub isolation: IsolationLevel,
+    /// How many times the work is retried after a serialization failure, before giving up
+    pub max_retries: u32,
+}
+
+impl Default for TransactionOptions {
+    fn default() -> Self {
+        Self {
+            isolation: IsolationLevel::default(),
+            max_retries: 3,
+        }
+    }
+}
+
+/// Repositories that all work inside the same transaction. Cloning is cheap, the clones share the
+/// transaction.
+#[derive(Clone, Debug)]
+pub struct UnitOfWork {
+    pub books: Arc<dyn BookRepository>,
+}
+
+/// Starts transactions on a storage backend
+#[async_trait]
+pub trait TransactionManager: Send + Sync + fmt::Debug {
+    /// Starts a transaction at least as isolated as `isolation`, see [`IsolationLevel`]
+    async fn begin(
+        &self,
+        isolation: IsolationLevel,
+    ) -> color_eyre::Result<(UnitOfWork, Box<dyn PendingTransaction>)>;
+}
+
+/// The transaction behind a [`UnitOfWork`], finished by whoever started it
+#[async_trait]
+pub trait PendingTransaction: Send {
+    async fn commit(self: Box<Self>) -> color_eyre::Result<()>;
+    async fn rollback(self: Box<Self>) -> color_eyre::Result<()>;
+}
+
+/// Returned by backends that detect conflicting transactions themselves, instead of relying on
+/// the database to do it
+#[derive(Debug)]
+pub struct SerializationFailure;
+
+impl fmt::Display for SerializationFailure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("The transaction conflicted with a concurrent one")
+    }
+}
+
+impl std::error::Error for SerializationFailure {}
+
+/// Whether running the same work again could succeed, because the transaction only failed due to
+/// a concurrent one
+pub fn is_serialization_failure(error: &color_eyre::Report) -> bool {
+    error.chain().any(|cause| {
+        if cause.is::<SerializationFailure>() {
+            return true;
+        }
+        let Some(sqlx::Error::Database(e)) = cause.downcast_ref::<sqlx::Error>() else {
+            return false;
+        };
+        // postgres: serialization_failure, deadlock_detected
+        // sqlite: SQLITE_BUSY, SQLITE_BUSY_SNAPSHOT
+        matches!(e.code().as_deref(), Some("40001" | "40P01" | "5" | "517"))
+    })
+}
@@ -1,10 +1,29 @@
-use std::borrow::Cow;
+use std::{borrow::Cow, panic::Location};
 
 use axum::{
     Json,
-    http::StatusCode,
+    extract::rejection::{JsonRejection, PathRejection},
+    http::{StatusCode, header},
     response::{IntoResponse, Response},
 };
+use serde::{Deserialize, Serialize};
+use tracing::error;
+use utoipa::ToSchema;
+
+tokio::task_local! {
+    /// Path of the request currently being handled, set by the tracing middleware. Used as the
+    /// `instance` of problem details, since `into_response` has no access to the request.
+    pub static REQUEST_PATH: String;
+    /// Who sent the request currently being handled, if it can be told, set by the tracing
+    /// middleware. Used to route the reads of a client to the primary after it wrote, and to
+    /// rate limit it.
+    pub static CLIENT: Option<String>;
+}
+
+/// The client of the request currently being handled, see [`CLIENT`]
+pub fn current_client() -> Option<String> {
+    CLIENT.try_with(Clone::clone).ok().flatten()
+}
 
 #[derive(Debug)]
 pub enum AxumHandlerError {
@@ -14,32 +33,128 @@ pub enum AxumHandlerError {
     BadRequest {
         msg: Cow<'static, str>,
     },
+    Conflict {
+        msg: Cow<'static, str>,
+    },
+    Validation {
+        errors: Vec<FieldError>,
+    },
+    /// The service can't serve requests right now, e.g. because the database schema is behind
+    Unavailable {
+        msg: Cow<'static, str>,
+    },
+    /// An extractor refused the request, e.g. because of a wrong content type or malformed JSON
+    Rejected {
+        status: StatusCode,
+        msg: String,
+    },
     Internal {
         msg: String,
         error: color_eyre::Report,
+        location: &'static Location<'static>,
     },
 }
 
+/// A single invalid field in a request body
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq, Eq, ToSchema)]
+pub struct FieldError {
+    pub field: String,
+    pub message: String,
+}
+
+/// RFC 7807 problem details, rendered as `application/problem+json`
+#[derive(Debug, Deserialize, Serialize, ToSchema)]
+pub struct ProblemDetails {
+    #[serde(rename = "type")]
+    pub kind: String,
+    pub title: String,
+    pub status: u16,
+    pub detail: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub instance: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub errors: Vec<FieldError>,
+}
+
+impl ProblemDetails {
+    pub fn new(status: StatusCode, detail: impl Into<String>) -> Self {
+        Self {
+            kind: "about:blank".to_string(),
+            title: status.canonical_reason().unwrap_or_default().to_string(),
+            status: status.as_u16(),
+            detail: detail.into(),
+            instance: REQUEST_PATH.try_with(Clone::clone).ok(),
+            errors: vec![],
+        }
+    }
+}
+
+impl IntoResponse for ProblemDetails {
+    fn into_response(self) -> Response {
+        // The status is a plain number, so it may have been set to one that isn't a status at all
+        let status = StatusCode::from_u16(self.status).unwrap_or(StatusCode::INTERNAL_SERVER_ERROR);
+        (
+            status,
+            [(header::CONTENT_TYPE, "application/problem+json")],
+            Json(self),
+        )
+            .into_response()
+    }
+}
+
 impl IntoResponse for AxumHandlerError {
     fn into_response(self) -> Response {
         match self {
             AxumHandlerError::NotFound { msg } => {
-                let e = (
-                    StatusCode::NOT_FOUND,
-                    Json(serde_json::json!({ "error": msg })),
-                );
-                e.into_response()
+                ProblemDetails::new(StatusCode::NOT_FOUND, msg).into_response()
+            }
+            AxumHandlerError::BadRequest { msg } => {
+                ProblemDetails::new(StatusCode::BAD_REQUEST, msg).into_response()
+            }
+            AxumHandlerError::Conflict { msg } => {
+                ProblemDetails::new(StatusCode::CONFLICT, msg).into_response()
+            }
+            AxumHandlerError::Validation { errors } => ProblemDetails {
+                errors,
+                ..ProblemDetails::new(
+                    StatusCode::UNPROCESSABLE_ENTITY,
+                    "The request body contains invalid fields",
+                )
             }
-            AxumHandlerError::Internal { msg, error: _ } => (
-                StatusCode::INTERNAL_SERVER_ERROR,
-                Json(serde_json::json!({"error": format!("Internal server error: {msg}")})),
-            )
-                .into_response(),
-            AxumHandlerError::BadRequest { msg } => (
-                StatusCode::BAD_REQUEST,
-                Json(serde_json::json!({ "error": msg })),
-            )
-                .into_response(),
+            .into_response(),
+            AxumHandlerError::Unavailable { msg } => {
+                ProblemDetails::new(StatusCode::SERVICE_UNAVAILABLE, msg).into_response()
+            }
+            AxumHandlerError::Rejected { status, msg } => {
+                ProblemDetails::new(status, msg).into_response()
+            }
+            AxumHandlerError::Internal {
+                msg,
+                error,
+                location,
+            } => {
+                let chain: Vec<String> = error.chain().map(ToString::to_string).collect();
+                error!(%location, error.chain = ?chain, "{msg}");
+                ProblemDetails::new(StatusCode::INTERNAL_SERVER_ERROR, msg).into_response()
+            }
+        }
+    }
+}
+
+impl From<JsonRejection> for AxumHandlerError {
+    fn from(rejection: JsonRejection) -> Self {
+        Self::Rejected {
+            status: rejection.status(),
+            msg: rejection.body_text(),
+        }
+    }
+}
+
+impl From<PathRejection> for AxumHandlerError {
+    fn from(rejection: PathRejection) -> Self {
+        Self::Rejected {
+            status: rejection.status(),
+            msg: rejection.body_text(),
         }
     }
 }
@@ -50,6 +165,7 @@ impl From<sqlx::Error> for AxumHandlerError {
         Self::Internal {
             msg: "Error while querying database".into(),
             error: e.into(),
+            location: Location::caller(),
         }
     }
 }
@@ -60,6 +176,22 @@ impl From<color_eyre::Report> for AxumHandlerError {
         Self::Internal {
             msg: "Unhandled internal error".into(),
             error: e,
+            location: Location::caller(),
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn problem_with_invalid_status_is_internal_error() {
+        let problem = ProblemDetails {
+            status: 42,
+            ..ProblemDetails::new(StatusCode::BAD_REQUEST, "Invalid")
+        };
+        let response = problem.into_response();
+        assert_eq!(response.status(), StatusCode::INTERNAL_SERVER_ERROR);
+    }
+}
@@ -0,0 +1,303 @@
+use axum::{
+    Json,
+    extract::{FromRequest, FromRequestParts, Path, Request},
+};
+use serde::de::DeserializeOwned;
+
+use crate::util::{AxumHandlerError, FieldError};
+
+/// Request payloads implement this to declare the rules their fields have to satisfy.
+///
+/// Validation gets mutable access to the payload, so rules like [`StringRules::trim`] can
+/// normalize the value before the rest of the rules look at it.
+pub trait Validate {
+    fn validate(&mut self, v: &mut Validator);
+}
+
+/// Collects every rule violation of a payload, instead of stopping at the first one
+#[derive(Debug, Default)]
+pub struct Validator {
+    errors: Vec<FieldError>,
+}
+
+impl Validator {
+    pub fn string<'a>(&'a mut self, field: &str, value: &'a mut String) -> StringRules<'a> {
+        StringRules {
+            validator: self,
+            field: field.to_string(),
+            value,
+        }
+    }
+
+    pub fn finish(self) -> Result<(), AxumHandlerError> {
+        if self.errors.is_empty() {
+            Ok(())
+        } else {
+            Err(AxumHandlerError::Validation {
+                errors: self.errors,
+            })
+        }
+    }
+
+    fn error(&mut self, field: String, message: impl Into<String>) {
+        self.errors.push(FieldError {
+            field,
+            message: message.into(),
+        });
+    }
+}
+
+pub struct StringRules<'a> {
+    validator: &'a mut Validator,
+    field: String,
+    value: &'a mut String,
+}
+
+impl StringRules<'_> {
+    /// Strips leading and trailing whitespace from the value, before the rest of the rules run
+    pub fn trim(self) -> Self {
+        let trimmed = self.value.trim();
+        if trimmed.len() != self.value.len() {
+            *self.value = trimmed.to_string();
+        }
+        self
+    }
+
+    /// The value must contain at least one non-whitespace character
+    pub fn required(mut self) -> Self {
+        if self.value.trim().is_empty() {
+            self.fail("is required");
+        }
+        self
+    }
+
+    pub fn max_length(mut self, max: usize) -> Self {
+        if self.value.chars().count() > max {
+            self.fail(format!("must be at most {max} characters long"));
+        }
+        self
+    }
+
+    /// Every character of the value must satisfy `allowed`. `description` tells the client what
+    /// was expected, e.g. "printable characters".
+    pub fn allowed_chars(mut self, allowed: impl Fn(char) -> bool, description: &str) -> Self {
+        if !self.value.chars().all(allowed) {
+            self.fail(format!("must only contain {description}"));
+        }
+        self
+    }
+
+    fn fail(&mut self, message: impl Into<String>) {
+        self.validator.error(self.field.clone(), message);
+    }
+}
+
+/// Like [`Json`], but runs the [`Validate`] rules of the payload, and reports rejections and
+/// violations as problem details instead of plain text.
+pub struct ValidatedJson<T>(pub T);
+
+impl<S, T> FromRequest<S> for ValidatedJson<T>
+where
+    S: Send + Sync,
+    T: DeserializeOwned + Validate,
+{
+    type Rejection = AxumHandlerError;
+
+    async fn from_request(req: Request, state: &S) -> Result<Self, Self::Rejection> {
+        // Going through a Value first lets axum handle the content type and syntax errors, while
+        // we still get to know which field failed to deserialize
+        let Json(json) = Json::<serde_json::Value>::from_request(req, state).await?;
+        let mut payload: T =
+            serde_path_to_error::deserialize(json).map_err(|e| AxumHandlerError::Validation {
+                errors: vec![FieldError {
+                    field: e.path().to_string(),
+                    message: e.inner().to_string(),
+                }],
+            })?;
+
+        let mut validator = Validator::default();
+        payload.validate(&mut validator);
+        validator.finish()?;
+
+        Ok(ValidatedJson(payload))
+    }
+}
+
+/// Like [`Path`], but reports rejections as problem details instead of plain text
+#[derive(FromRequestParts)]
+#[from_request(via(Path), rejection(AxumHandlerError))]
+pub struct PathParams<T>(pub T);
+
+#[cfg(test)]
+mod tests {
+    use axum::{body::Body, http::StatusCode};
+    use serde::Deserialize;
+
+    use super::*;
+
+    fn field_error(field: &str, message: &str) -> FieldError {
+        FieldError {
+            field: field.to_string(),
+            message: message.to_string(),
+        }
+    }
+
+    fn errors(validator: Validator) -> Vec<FieldError> {
+        match validator.finish() {
+            Err(AxumHandlerError::Validation { errors }) => errors,
+            other => panic!("Expected validation errors, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn trim_strips_the_value_before_the_other_rules() {
+        let mut validator = Validator::default();
+        let mut value = String::from("  Dune \n");
+        validator.string("name", &mut value).trim().max_length(4);
+        assert_eq!(value, "Dune");
+        assert!(validator.finish().is_ok());
+    }
+
+    #[test]
+    fn required_rejects_empty_and_blank_values() {
+        for blank in ["", " \t\n"] {
+            let mut validator = Validator::default();
+            let mut value = String::from(blank);
+            validator.string("name", &mut value).required();
+            assert_eq!(errors(validator), [field_error("name", "is required")]);
+        }
+
+        let mut validator = Validator::default();
+        let mut value = String::from(" x ");
+        validator.string("name", &mut value).required();
+        assert!(validator.finish().is_ok());
+    }
+
+    #[test]
+    fn max_length_counts_characters() {
+        let mut validator = Validator::default();
+        let mut value = String::from("ÆØÅ");
+        validator.string("name", &mut value).max_length(3);
+        assert!(validator.finish().is_ok());
+
+        let mut validator = Validator::default();
+        let mut value = String::from("ÆØÅÆ");
+        validator.string("name", &mut value).max_length(3);
+        assert_eq!(
+            errors(validator),
+            [field_error("name", "must be at most 3 characters long")]
+        );
+    }
+
+    #[test]
+    fn allowed_chars_rejects_any_other_character() {
+        let mut validator = Validator::default();
+        let mut value = String::from("Dune\u{7}");
+        validator
+            .string("name", &mut value)
+            .allowed_chars(|c| !c.is_control(), "printable characters");
+        assert_eq!(
+            errors(validator),
+            [field_error(
+                "name",
+                "must only contain printable characters"
+            )]
+        );
+    }
+
+    #[test]
+    fn every_violation_is_reported_in_order() {
+        let mut validator = Validator::default();
+        let mut name = String::from("Dune\u{0}Messiah");
+        let mut description = String::from("   ");
+        validator
+            .string("name", &mut name)
+            .required()
+            .max_length(5)
+            .allowed_chars(|c| !c.is_control(), "printable characters");
+        validator
+            .string("description", &mut description)
+            .trim()
+            .required();
+        assert_eq!(
+            errors(validator),
+            [
+                field_error("name", "must be at most 5 characters long"),
+                field_error("name", "must only contain printable characters"),
+                field_error("description", "is required"),
+            ]
+        );
+    }
+
+    #[derive(Debug, Deserialize)]
+    struct Edition {
+        title: String,
+        author: Author,
+    }
+
+    #[derive(Debug, Deserialize)]
+    struct Author {
+        born: u16,
+    }
+
+    impl Validate for Edition {
+        fn validate(&mut self, v: &mut Validator) {
+            v.string("title", &mut self.title).trim().required();
+        }
+    }
+
+    async fn extract(content_type: &str, body: &str) -> Result<Edition, AxumHandlerError> {
+        let request = Request::post("/")
+            .header("content-type", content_type)
+            .body(Body::from(body.to_string()))
+            .unwrap();
+        ValidatedJson::<Edition>::from_request(request, &())
+            .await
+            .map(|ValidatedJson(edition)| edition)
+    }
+
+    #[tokio::test]
+    async fn validated_json_runs_the_rules() {
+        let body = r#"{"title": " Dune ", "author": {"born": 1920}}"#;
+        let edition = extract("application/json", body).await.unwrap();
+        assert_eq!(edition.title, "Dune");
+        assert_eq!(edition.author.born, 1920);
+
+        let body = r#"{"title": " ", "author": {"born": 1920}}"#;
+        match extract("application/json", body).await {
+            Err(AxumHandlerError::Validation { errors }) => {
+                assert_eq!(errors, [field_error("title", "is required")])
+            }
+            other => panic!("Expected validation errors, got {other:?}"),
+        }
+    }
+
+    #[tokio::test]
+    async fn validated_json_reports_the_path_of_nested_fields() {
+        let body = r#"{"title": "Dune", "author": {"born": "1920"}}"#;
+        match extract("application/json", body).await {
+            Err(AxumHandlerError::Validation { errors }) => {
+                assert_eq!(errors.len(), 1);
+                assert_eq!(errors[0].field, "author.born");
+                assert!(errors[0].message.starts_with("invalid type: string"));
+            }
+            other => panic!("Expected validation errors, got {other:?}"),
+        }
+    }
+
+    #[tokio::test]
+    async fn validated_json_rejects_other_content_and_malformed_json() {
+        match extract("text/plain", "{}").await {
+            Err(AxumHandlerError::Rejected { status, .. }) => {
+                assert_eq!(status, StatusCode::UNSUPPORTED_MEDIA_TYPE)
+            }
+            other => panic!("Expected a rejection, got {other:?}"),
+        }
+        match extract("application/json", r#"{"title": "#).await {
+            Err(AxumHandlerError::Rejected { status, .. }) => {
+                assert_eq!(status, StatusCode::BAD_REQUEST)
+            }
+            other => panic!("Expected a rejection, got {other:?}"),
+        }
+    }
+}
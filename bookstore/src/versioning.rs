@@ -0,0 +1,91 @@
+use std::time::{Duration, SystemTime};
+
+use axum::{
+    http::{HeaderValue, header::HeaderName},
+    middleware,
+    response::Response,
+};
+use utoipa::openapi::{Deprecated, OpenApi};
+use utoipa_axum::router::OpenApiRouter;
+
+use crate::appstate::AppState;
+
+/// A set of routes mounted under a common prefix, next to the other versions of the API
+pub struct ApiVersion {
+    /// Used to keep the operation ids of different versions apart in the OpenAPI document
+    pub name: &'static str,
+    /// Path prefix the version is mounted under, e.g. `/v1`. Empty mounts the version at the root.
+    pub prefix: &'static str,
+    pub routes: fn() -> OpenApiRouter<AppState>,
+    pub deprecation: Option<Deprecation>,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct Deprecation {
+    /// When the version got deprecated, in seconds since the unix epoch
+    pub since: u64,
+    /// When the version is going to be removed, in seconds since the unix epoch
+    pub sunset: Option<u64>,
+}
+
+/// Mounts every version into a single router, so they are all served at the same time
+pub fn mount(versions: &[ApiVersion]) -> OpenApiRouter<AppState> {
+    versions
+        .iter()
+        .fold(OpenApiRouter::new(), |router, version| {
+            let mut routes = (version.routes)();
+            annotate_operations(routes.get_openapi_mut(), version);
+            if let Some(deprecation) = version.deprecation {
+                routes = routes.layer(middleware::map_response(move |res| {
+                    deprecation_headers(res, deprecation)
+                }));
+            }
+
+            if version.prefix.is_empty() {
+                router.merge(routes)
+            } else {
+                router.nest(version.prefix, routes)
+            }
+        })
+}
+
+fn annotate_operations(openapi: &mut OpenApi, version: &ApiVersion) {
+    for item in openapi.paths.paths.values_mut() {
+        let operations = [
+            &mut item.get,
+            &mut item.put,
+            &mut item.post,
+            &mut item.delete,
+            &mut item.options,
+            &mut item.head,
+            &mut item.patch,
+            &mut item.trace,
+        ];
+        for operation in operations.into_iter().flatten() {
+            if let Some(id) = operation.operation_id.as_mut() {
+                *id = format!("{}_{id}", version.name);
+            }
+            if version.deprecation.is_some() {
+                operation.deprecated = Some(Deprecated::True);
+            }
+        }
+    }
+}
+
+async fn deprecation_headers(mut res: Response, deprecation: Deprecation) -> Response {
+    let headers = res.headers_mut();
+    // RFC 9745 wants a structured field date, which is the unix timestamp prefixed with @
+    // safety: both values only contain digits, letters, spaces, commas, colons and @
+    headers.insert(
+        HeaderName::from_static("deprecation"),
+        HeaderValue::from_str(&format!("@{}", deprecation.since)).unwrap(),
+    );
+    if let Some(sunset) = deprecation.sunset {
+        let date = SystemTime::UNIX_EPOCH + Duration::from_secs(sunset);
+        headers.insert(
+            HeaderName::from_static("sunset"),
+            HeaderValue::from_str(&httpdate::fmt_http_date(date)).unwrap(),
+        );
+    }
+    res
+}